@@ -1,16 +1,467 @@
+use crate::allowlist;
+use crate::builtin;
+use crate::clock;
+use crate::config::{
+    Action, ActionKind, ArchiveFormat, Config, ExecMode, LogActionOutput, NonUtf8Policy,
+    WatchConfig,
+};
 use crate::errors::{AppError, Result};
-use std::path::Path;
+use crate::ownership::{self, Credentials};
+use crate::pool::{self, PooledCommand};
+use notify::EventKind;
+use notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::DebouncedEvent;
+use regex::Regex;
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::process::Stdio;
+use std::time::{Duration, Instant, SystemTime};
+use tempfile::TempPath;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::process::Command;
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
 
-#[instrument(skip(command_template), fields(command = %command_template, path = %path.display()))]
-pub async fn execute_action(command_template: &str, path: &Path) -> Result<()> {
-    let path_str = path
-        .to_str()
-        .ok_or_else(|| AppError::PathNonUtf8(path.to_path_buf()))?;
+const MAX_INLINE_COMMAND_BYTES: usize = 32 * 1024;
+const MAX_HOOK_DEPTH: u32 = 8;
 
-    let command_to_run = command_template.replace("{}", path_str);
+#[derive(Debug, Clone, Default)]
+pub struct ActionContext {
+    pub rename_from: Option<PathBuf>,
+    pub rename_to: Option<PathBuf>,
+    pub watch_name: String,
+    pub watch_index: usize,
+    pub watch_root: Option<PathBuf>,
+    pub event_kind: Option<EventKind>,
+    // 0-based; the same invocation id is kept across retries.
+    pub attempt: u32,
+    pub invocation_id: String,
+    // Correlation id of the event being handled, which also keys its history entry.
+    pub event_id: Option<String>,
+    // The appended line being processed, for `tail` watches.
+    pub line: Option<String>,
+    // What a parent action's `capture-regex` matched, for its `then` action.
+    pub capture: Option<String>,
+    // Levels of the path captured by the watch's `path-template`, as (name, value).
+    pub segments: Vec<(String, String)>,
+    // Why the action failed, for its `on-failure` hook. `exit_code` is only set
+    // for commands that exited non-zero.
+    pub error: Option<String>,
+    pub exit_code: Option<i32>,
+    // How many hooks deep this action runs.
+    pub hook_depth: u32,
+    // Everything collected by a digest action, empty otherwise.
+    pub paths: Vec<PathBuf>,
+    pub old_target: Option<PathBuf>,
+    pub new_target: Option<PathBuf>,
+    // When the change happened, as recorded by the debouncer. `{timestamp}` falls
+    // back to the time the action runs when this is unset.
+    pub event_time: Option<SystemTime>,
+}
+
+impl ActionContext {
+    pub fn for_event(event: &DebouncedEvent) -> Self {
+        let mut context = ActionContext {
+            event_time: Some(clock::wall_clock(event.time)),
+            event_kind: Some(event.kind),
+            ..ActionContext::default()
+        };
+        if let EventKind::Modify(ModifyKind::Name(mode)) = event.kind {
+            match (mode, event.paths.as_slice()) {
+                (RenameMode::Both, [from, to]) => {
+                    context.rename_from = Some(from.clone());
+                    context.rename_to = Some(to.clone());
+                }
+                (RenameMode::From, [from, ..]) => context.rename_from = Some(from.clone()),
+                (RenameMode::To, [to, ..]) => context.rename_to = Some(to.clone()),
+                _ => {}
+            }
+        }
+        context
+    }
+
+    pub fn for_watch(&self, index: usize, watch: &WatchConfig) -> Self {
+        ActionContext {
+            watch_name: watch.display_name().to_string(),
+            watch_index: index,
+            watch_root: watch.resolved_root().map(Path::to_path_buf),
+            ..self.clone()
+        }
+    }
+
+    // For an action on one of the event's paths, with the levels captured by
+    // the watch's `path-template`.
+    pub fn for_path(&self, index: usize, watch: &WatchConfig, path: &Path) -> Self {
+        let mut context = self.for_watch(index, watch);
+        if let (Some(template), Some(root)) = (&watch.path_template, &context.watch_root) {
+            context.segments = template.captures(path, root).unwrap_or_default();
+        }
+        context
+    }
+}
+
+// Carries out a matched action on a path. Everything before it (matching,
+// sampling, rate limits, digests) and after it (quiet periods, dead letters,
+// fail-fast) stays with the caller; retries are part of the execution, while
+// `then` chains and hooks come back through the executor as actions of their
+// own. An executor could hand actions to a job system instead of running
+// them here.
+pub trait ActionExecutor: Send + Sync {
+    fn execute<'a>(
+        &'a self,
+        config: &'a Config,
+        watch: &'a WatchConfig,
+        action: &'a Action,
+        path: &'a Path,
+        context: &'a ActionContext,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+}
+
+// Runs commands through the shell or pool and built-in actions in-process.
+pub struct ShellExecutor;
+
+impl ActionExecutor for ShellExecutor {
+    fn execute<'a>(
+        &'a self,
+        config: &'a Config,
+        watch: &'a WatchConfig,
+        action: &'a Action,
+        path: &'a Path,
+        context: &'a ActionContext,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(execute_action(config, watch, action, path, context))
+    }
+}
+
+// The executor every action goes through.
+pub fn executor() -> &'static dyn ActionExecutor {
+    &ShellExecutor
+}
+
+#[instrument(skip(config, watch, action, context), fields(action = %action.describe(), path = %path.display()))]
+async fn execute_action(
+    config: &Config,
+    watch: &WatchConfig,
+    action: &Action,
+    path: &Path,
+    context: &ActionContext,
+) -> Result<()> {
+    let result = match run_attempts(config, watch, action, path, context).await {
+        Ok(None) => return Ok(()),
+        Ok(Some(stdout)) => run_then(config, watch, action, path, context, &stdout).await,
+        Err(e) => Err(e),
+    };
+    run_hooks(config, watch, action, path, context, &result).await;
+    result
+}
+
+// Runs the action with its retries. Returns the command's stdout, or None if
+// the action was skipped without running.
+async fn run_attempts(
+    config: &Config,
+    watch: &WatchConfig,
+    action: &Action,
+    path: &Path,
+    context: &ActionContext,
+) -> Result<Option<String>> {
+    if action.kind == ActionKind::Command && action.shell_source(context.event_kind).is_none() {
+        debug!("Command table has no entry for this event, skipping action");
+        return Ok(None);
+    }
+    // The invocation id stays the same across retries so commands can tell a
+    // retry from a new trigger; the operation is re-rendered for each attempt.
+    let mut context = ActionContext {
+        attempt: 0,
+        invocation_id: uuid::Uuid::new_v4().to_string(),
+        ..context.clone()
+    };
+    let mut operation = match prepare_operation(config, watch, action, path, &context) {
+        Err(AppError::PathNonUtf8(path)) if config.on_non_utf8 == NonUtf8Policy::Skip => {
+            info!(path = ?path, "Path is not valid UTF-8, skipping action");
+            return Ok(None);
+        }
+        operation => operation?,
+    };
+
+    if let Some(predicate) = &action.when_command {
+        let guard = prepare_shell(config, watch, action, predicate, false, path, &context)?;
+        if !predicate_passes(config, &guard).await? {
+            debug!(when_command = %guard.command_line, "Predicate exited non-zero, skipping action");
+            return Ok(None);
+        }
+    }
+
+    info!("Executing action");
+
+    let budget = action.max_total_duration_ms.map(Duration::from_millis);
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let result = match budget {
+            Some(budget) => {
+                let remaining = budget.saturating_sub(started.elapsed());
+                tokio::time::timeout(remaining, operation.run(config))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(budget_exhausted(&operation.describe(), attempt + 1, budget))
+                    })
+            }
+            None => operation.run(config).await,
+        };
+        match result {
+            Err(e) if attempt < action.retries && retryable(action, &e) => {
+                if let Some(budget) = budget
+                    && started.elapsed() >= budget
+                {
+                    return Err(budget_exhausted(&operation.describe(), attempt + 1, budget));
+                }
+                attempt += 1;
+                warn!(attempt, retries = action.retries, error = %e, "Action failed, retrying");
+                context.attempt = attempt;
+                operation = prepare_operation(config, watch, action, path, &context)?;
+            }
+            result => return result.map(Some),
+        }
+    }
+}
+
+// Hooks run on the same path and event, and only log their own failures. A
+// hook that (through named actions) ends up triggering itself is cut off after
+// MAX_HOOK_DEPTH levels.
+async fn run_hooks(
+    config: &Config,
+    watch: &WatchConfig,
+    action: &Action,
+    path: &Path,
+    context: &ActionContext,
+    result: &Result<()>,
+) {
+    let (hook, name, context) = match result {
+        Ok(()) => (&action.on_success, "on-success", context.clone()),
+        Err(e) => (
+            &action.on_failure,
+            "on-failure",
+            ActionContext {
+                error: Some(e.to_string()),
+                exit_code: match e {
+                    AppError::CommandFailed { code, .. } => *code,
+                    _ => None,
+                },
+                ..context.clone()
+            },
+        ),
+    };
+    let Some(hook) = hook else {
+        return;
+    };
+    if context.hook_depth >= MAX_HOOK_DEPTH {
+        warn!(
+            hook = name,
+            depth = context.hook_depth,
+            "Hooks nested too deeply, not running hook; does it trigger itself?"
+        );
+        return;
+    }
+    let Some(hook_action) = hook.resolve(&config.named_actions) else {
+        return;
+    };
+    let context = ActionContext {
+        hook_depth: context.hook_depth + 1,
+        ..context
+    };
+    if let Err(e) = executor()
+        .execute(config, watch, hook_action, path, &context)
+        .await
+    {
+        warn!(hook = name, error = %e, "Hook action failed");
+    }
+}
+
+async fn run_then(
+    config: &Config,
+    watch: &WatchConfig,
+    action: &Action,
+    path: &Path,
+    context: &ActionContext,
+    stdout: &str,
+) -> Result<()> {
+    let (Some(pattern), Some(then)) = (&action.capture_regex, &action.then) else {
+        return Ok(());
+    };
+    // Checked when the config was validated.
+    let Ok(regex) = Regex::new(pattern) else {
+        return Ok(());
+    };
+    let Some(captures) = regex.captures(stdout) else {
+        debug!(capture_regex = %pattern, "Output did not match capture-regex, skipping chained action");
+        return Ok(());
+    };
+    let capture = captures
+        .get(1)
+        .or_else(|| captures.get(0))
+        .map_or("", |capture| capture.as_str());
+    let context = ActionContext {
+        capture: Some(capture.to_string()),
+        ..context.clone()
+    };
+    executor()
+        .execute(config, watch, then, path, &context)
+        .await
+}
+
+fn retryable(action: &Action, error: &AppError) -> bool {
+    match error {
+        AppError::ActionBudgetExhausted { .. } => false,
+        _ if action.retry_on_codes.is_empty() => true,
+        AppError::CommandFailed {
+            code: Some(code), ..
+        } => action.retry_on_codes.contains(code),
+        _ => false,
+    }
+}
+
+fn prepare_operation(
+    config: &Config,
+    watch: &WatchConfig,
+    action: &Action,
+    path: &Path,
+    context: &ActionContext,
+) -> Result<Operation> {
+    let operation = match action.kind {
+        ActionKind::Command => Operation::Shell(Box::new(prepare_shell(
+            config,
+            watch,
+            action,
+            action.shell_source(context.event_kind).unwrap_or_default(),
+            action.script.is_some(),
+            path,
+            context,
+        )?)),
+        kind => {
+            let destination = match &action.destination {
+                Some(template) => Some(PathBuf::from(render_template(
+                    template,
+                    &path_text(path, config.on_non_utf8)?,
+                    context,
+                    config.on_non_utf8,
+                )?)),
+                None => None,
+            };
+            Operation::Builtin {
+                kind,
+                source: path.to_path_buf(),
+                destination,
+                overwrite: action.overwrite,
+                archive_format: action.archive_format,
+                delete_original: action.delete_original,
+            }
+        }
+    };
+    Ok(operation)
+}
+
+enum Operation {
+    Shell(Box<ShellCommand>),
+    Builtin {
+        kind: ActionKind,
+        source: PathBuf,
+        destination: Option<PathBuf>,
+        overwrite: bool,
+        archive_format: ArchiveFormat,
+        delete_original: bool,
+    },
+}
+
+struct ShellCommand {
+    command_line: String,
+    working_dir: Option<PathBuf>,
+    envs: Vec<(String, String)>,
+    // Replaces kadesh's own environment when `env-allow`/`env-deny` are set.
+    inherited_env: Option<Vec<(OsString, OsString)>>,
+    max_output_bytes: Option<usize>,
+    merge_output: bool,
+    inherit_output: bool,
+    // Interpreter and its arguments, e.g. `sh -c`.
+    shell: Vec<String>,
+    // Kept alive for the duration of the action; removed on drop.
+    script: Option<TempPath>,
+    // The script is executable and runs with its own interpreter.
+    shebang: bool,
+    umask: Option<u32>,
+    credentials: Option<Credentials>,
+    // List of digest paths behind `$KADESH_PATHS_FILE`; removed on drop.
+    _manifest: Option<TempPath>,
+}
+
+impl Operation {
+    // Returns the captured stdout of a command, empty for built-in actions.
+    async fn run(&mut self, config: &Config) -> Result<String> {
+        match self {
+            Operation::Shell(shell) if shell.pooled(config) => run_pooled(config, shell).await,
+            Operation::Shell(shell) => run_command(config, shell).await,
+            Operation::Builtin {
+                kind,
+                source,
+                destination,
+                overwrite,
+                archive_format,
+                delete_original,
+            } => {
+                builtin::run(
+                    *kind,
+                    source,
+                    destination.as_deref(),
+                    *overwrite,
+                    *archive_format,
+                    *delete_original,
+                )
+                .await?;
+                Ok(String::new())
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Operation::Shell(shell) => shell.command_line.clone(),
+            Operation::Builtin {
+                kind,
+                source,
+                destination,
+                ..
+            } => match destination {
+                Some(destination) => format!(
+                    "{} {} -> {}",
+                    kind.as_str(),
+                    source.display(),
+                    destination.display()
+                ),
+                None => format!("{} {}", kind.as_str(), source.display()),
+            },
+        }
+    }
+}
+
+fn pooled(config: &Config) -> bool {
+    config.exec_mode == ExecMode::Pool && cfg!(unix)
+}
+
+fn prepare_shell(
+    config: &Config,
+    watch: &WatchConfig,
+    action: &Action,
+    source: &str,
+    is_script: bool,
+    path: &Path,
+    context: &ActionContext,
+) -> Result<ShellCommand> {
+    let (target, working_dir) = resolve_target(action, path);
+    let policy = config.on_non_utf8;
+    let path_str = path_text(target, policy)?;
+
+    let command_to_run = render_template(source, &path_str, context, policy)?;
 
     if command_to_run.trim().is_empty() {
         return Err(AppError::EmptyCommand {
@@ -19,44 +470,801 @@ pub async fn execute_action(command_template: &str, path: &Path) -> Result<()> {
         });
     }
 
-    info!("Executing action");
+    if let Some(allowlist) = &config.command_allowlist {
+        allowlist::check(allowlist, &command_to_run, is_script)?;
+    }
+
     debug!("Running command: {}", command_to_run);
 
-    let mut command = if cfg!(target_os = "windows") {
-        let mut cmd = Command::new("cmd");
-        cmd.args(["/C", &command_to_run]);
-        cmd
+    // `script` bodies always run from a temporary file, directly when they have a
+    // shebang and through the shell otherwise. Oversized commands would hit the
+    // OS argument length limit, so they are run the same way. The file is removed
+    // when it goes out of scope, whatever the outcome. Pooled workers read
+    // commands from stdin and have no such limit, so only shebang scripts need a
+    // file there.
+    let credentials = run_as(config, action)?;
+    let spawned = !pooled(config) || credentials.is_some() || action.inherit_output;
+    let shebang = is_script && command_to_run.starts_with("#!") && cfg!(unix);
+    let needs_file =
+        shebang || (spawned && (is_script || command_to_run.len() > MAX_INLINE_COMMAND_BYTES));
+    let script = if needs_file {
+        debug!(
+            bytes = command_to_run.len(),
+            shebang, "Running command from a temporary script"
+        );
+        let script = write_temp_script(&command_to_run, shebang)?;
+        hand_over(&script, credentials)?;
+        Some(script)
     } else {
-        let mut cmd = Command::new("sh");
-        cmd.args(["-c", &command_to_run]);
-        cmd
+        None
     };
 
-    command.stdin(Stdio::null());
-    command.stdout(Stdio::piped());
-    command.stderr(Stdio::piped());
+    let mut envs = vec![
+        ("KADESH_WATCH".to_string(), context.watch_name.clone()),
+        (
+            "KADESH_WATCH_INDEX".to_string(),
+            context.watch_index.to_string(),
+        ),
+    ];
+    if let Some(root) = &context.watch_root {
+        envs.push((
+            "KADESH_WATCH_ROOT".to_string(),
+            path_text(root, policy)?.into_owned(),
+        ));
+    }
+    envs.push(("KADESH_ATTEMPT".to_string(), context.attempt.to_string()));
+    envs.push((
+        "KADESH_INVOCATION_ID".to_string(),
+        context.invocation_id.clone(),
+    ));
+    if let Some(line) = &context.line {
+        envs.push(("KADESH_LINE".to_string(), line.clone()));
+    }
+    if let Some(capture) = &context.capture {
+        envs.push(("KADESH_CAPTURE".to_string(), capture.clone()));
+    }
+    if let Some(error) = &context.error {
+        envs.push(("KADESH_ERROR".to_string(), error.clone()));
+    }
+    if let Some(code) = context.exit_code {
+        envs.push(("KADESH_EXIT_CODE".to_string(), code.to_string()));
+    }
+    let manifest = if context.paths.is_empty() {
+        None
+    } else {
+        let manifest = write_manifest(&context.paths, policy)?;
+        hand_over(&manifest, credentials)?;
+        envs.push((
+            "KADESH_PATHS_FILE".to_string(),
+            path_to_str(&manifest)?.to_string(),
+        ));
+        Some(manifest)
+    };
+    // Action entries are applied last so they override the watch-level ones.
+    for (key, value) in watch.env.iter().chain(&action.env) {
+        envs.push((
+            key.clone(),
+            render_template(value, &path_str, context, policy)?,
+        ));
+    }
+
+    Ok(ShellCommand {
+        command_line: command_to_run,
+        working_dir: working_dir.map(Path::to_path_buf),
+        envs,
+        inherited_env: inherited_env(config),
+        max_output_bytes: action.max_output_bytes.or(config.max_output_bytes),
+        merge_output: action.merge_output,
+        inherit_output: action.inherit_output,
+        umask: action
+            .umask
+            .as_deref()
+            .or(config.umask.as_deref())
+            .and_then(parse_umask),
+        credentials,
+        shell: config.shell_argv(),
+        script,
+        shebang,
+        _manifest: manifest,
+    })
+}
+
+impl ShellCommand {
+    // Pool workers run as kadesh's user and their output is always captured.
+    fn pooled(&self, config: &Config) -> bool {
+        pooled(config) && self.credentials.is_none() && !self.inherit_output
+    }
 
-    let output = command.output().await.map_err(|e| AppError::ActionExec {
-        command: command_to_run.clone(),
-        source: e,
-    })?;
+    fn command(&self) -> Command {
+        let (program, args) = self
+            .shell
+            .split_first()
+            .expect("shell_argv always names an interpreter");
+        let mut command = match &self.script {
+            Some(script) if self.shebang => Command::new(script),
+            // `cmd` needs its `/C` to run a script; POSIX shells take the file
+            // directly in place of `-c`.
+            Some(script) => {
+                let mut cmd = Command::new(program);
+                if cfg!(target_os = "windows") {
+                    cmd.args(args);
+                }
+                cmd.arg(script);
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(program);
+                cmd.args(args).arg(&self.command_line);
+                cmd
+            }
+        };
 
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        if !stdout.trim().is_empty() {
-            debug!(output = %stdout.trim(), "Command executed successfully");
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+        if let Some(inherited) = &self.inherited_env {
+            command.env_clear();
+            command.envs(inherited.iter().map(|(key, value)| (key, value)));
+        }
+        command.envs(self.envs.iter().map(|(key, value)| (key, value)));
+        command.stdin(Stdio::null());
+        if self.inherit_output {
+            command.stdout(Stdio::inherit());
+            command.stderr(Stdio::inherit());
         } else {
-            debug!("Command executed successfully (no output)");
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
         }
-        Ok(())
+        command.kill_on_drop(true);
+        #[cfg(unix)]
+        if let Some(mask) = self.umask {
+            // SAFETY: the closure runs in the forked child before exec, where
+            // only async-signal-safe calls are allowed. umask(2) is one, it
+            // does not allocate or take locks, and it cannot fail.
+            unsafe {
+                command.pre_exec(move || {
+                    libc::umask(mask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(unix)]
+        if let Some(credentials) = self.credentials {
+            // SAFETY: `drop_privileges` only makes async-signal-safe syscalls,
+            // as required between fork and exec. An error fails the spawn.
+            unsafe {
+                command.pre_exec(move || ownership::drop_privileges(credentials));
+            }
+        }
+        command
+    }
+}
+
+fn run_as(config: &Config, action: &Action) -> Result<Option<Credentials>> {
+    let user = action.run_as_user.or(config.run_as_user);
+    let group = action.run_as_group.or(config.run_as_group);
+    if (user.is_some() || group.is_some()) && !cfg!(unix) {
+        return Err(AppError::ConfigValidation(
+            "run-as-user and run-as-group are only supported on Unix".to_string(),
+        ));
+    }
+    let gid = match (group, user) {
+        (Some(group), _) => group.0,
+        (None, Some(user)) => ownership::primary_group(user.0).ok_or_else(|| {
+            AppError::ConfigValidation(format!("run-as-user {} has no passwd entry", user.0))
+        })?,
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Credentials {
+        uid: user.map(|user| user.0),
+        gid,
+    }))
+}
+
+// Temporary files are private to kadesh's user; one read by a command running
+// as someone else is given to that user.
+fn hand_over(file: &Path, credentials: Option<Credentials>) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(credentials) = credentials {
+        std::os::unix::fs::chown(file, credentials.uid, Some(credentials.gid))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (file, credentials);
+    Ok(())
+}
+
+fn parse_umask(value: &str) -> Option<u32> {
+    if !cfg!(unix) {
+        warn!(
+            umask = value,
+            "umask is only supported on Unix, ignoring it"
+        );
+        return None;
+    }
+    match u32::from_str_radix(value, 8) {
+        Ok(mask) if mask <= 0o777 => Some(mask),
+        _ => {
+            warn!(
+                umask = value,
+                "Invalid umask, expected octal like \"022\"; keeping the inherited one"
+            );
+            None
+        }
+    }
+}
+
+// The part of kadesh's environment actions inherit, or None to pass it all.
+// Names that aren't UTF-8 can't match a glob and are only passed unfiltered.
+fn inherited_env(config: &Config) -> Option<Vec<(OsString, OsString)>> {
+    if config.env_allow.is_none() && config.env_deny.is_empty() {
+        return None;
+    }
+    Some(
+        std::env::vars_os()
+            .filter(|(key, _)| key.to_str().is_some_and(|key| config.passes_env(key)))
+            .collect(),
+    )
+}
+
+// Only a failure to start the predicate is an error; any exit status is an answer.
+async fn predicate_passes(config: &Config, shell: &ShellCommand) -> Result<bool> {
+    let command_to_run = shell.command_line.as_str();
+    if shell.pooled(config) {
+        let command = PooledCommand {
+            command_line: command_to_run,
+            working_dir: shell.working_dir.as_ref(),
+            envs: &shell.envs,
+            inherited_env: shell.inherited_env.as_deref(),
+            umask: shell.umask,
+        };
+        let output = pool::shared(config.pool_size)
+            .run(&command)
+            .await
+            .map_err(|e| AppError::ActionExec {
+                command: command_to_run.to_string(),
+                source: e,
+            })?;
+        return Ok(output.code == 0);
+    }
+    let output = shell
+        .command()
+        .output()
+        .await
+        .map_err(|e| spawn_error(command_to_run, e))?;
+    Ok(output.status.success())
+}
+
+async fn run_pooled(config: &Config, shell: &ShellCommand) -> Result<String> {
+    let invocation = match &shell.script {
+        Some(script) if shell.shebang => pool::quote(path_to_str(script)?),
+        _ => shell.command_line.clone(),
+    };
+    let command = PooledCommand {
+        command_line: &invocation,
+        working_dir: shell.working_dir.as_ref(),
+        envs: &shell.envs,
+        inherited_env: shell.inherited_env.as_deref(),
+        umask: shell.umask,
+    };
+    let output = pool::shared(config.pool_size)
+        .run(&command)
+        .await
+        .map_err(|e| AppError::ActionExec {
+            command: shell.command_line.clone(),
+            source: e,
+        })?;
+    let limit = shell.max_output_bytes.unwrap_or(usize::MAX);
+    let stdout = CapturedOutput::truncated(output.output, limit);
+    report_output(
+        config,
+        &shell.command_line,
+        Some(output.code),
+        &stdout,
+        &CapturedOutput::default(),
+    )?;
+    Ok(stdout.text())
+}
+
+async fn run_command(config: &Config, shell: &ShellCommand) -> Result<String> {
+    let command_to_run = shell.command_line.as_str();
+    let exec_error = |source| AppError::ActionExec {
+        command: command_to_run.to_string(),
+        source,
+    };
+    let mut command = shell.command();
+    #[cfg(unix)]
+    let merged = if shell.merge_output {
+        Some(merge_streams(&mut command).map_err(exec_error)?)
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        if !stderr.trim().is_empty() {
-            debug!(stderr = %stderr.trim(), "Command stderr output");
+        None
+    };
+    #[cfg(not(unix))]
+    let merged: Option<tokio::process::ChildStdout> = None;
+    let mut child = command
+        .spawn()
+        .map_err(|e| spawn_error(command_to_run, e))?;
+    // The command holds our copies of the merged pipe's write end; it has to go
+    // for the reader to see EOF once the child exits.
+    drop(command);
+
+    // Output is read as it is produced and anything past the limit is counted
+    // but not kept, so a chatty command cannot balloon memory either.
+    let limit = shell.max_output_bytes.unwrap_or(usize::MAX);
+    let (stdout, stderr, status) = match merged {
+        Some(merged) => tokio::try_join!(
+            capture(Some(merged), limit),
+            capture(child.stderr.take(), limit),
+            child.wait(),
+        ),
+        None => tokio::try_join!(
+            capture(child.stdout.take(), limit),
+            capture(child.stderr.take(), limit),
+            child.wait(),
+        ),
+    }
+    .map_err(exec_error)?;
+    report_output(config, command_to_run, status.code(), &stdout, &stderr)?;
+    Ok(stdout.text())
+}
+
+// Points stdout and stderr at one pipe, like `2>&1`, so output stays interleaved.
+#[cfg(unix)]
+fn merge_streams(command: &mut Command) -> std::io::Result<tokio::net::unix::pipe::Receiver> {
+    let (reader, writer) = std::io::pipe()?;
+    command.stdout(writer.try_clone()?);
+    command.stderr(writer);
+    tokio::net::unix::pipe::Receiver::from_owned_fd(reader.into())
+}
+
+#[derive(Default)]
+struct CapturedOutput {
+    bytes: Vec<u8>,
+    dropped: usize,
+}
+
+impl CapturedOutput {
+    fn truncated(mut bytes: Vec<u8>, limit: usize) -> Self {
+        let dropped = bytes.len().saturating_sub(limit);
+        bytes.truncate(limit);
+        CapturedOutput { bytes, dropped }
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes).into_owned()
+    }
+
+    fn render(&self) -> String {
+        let text = String::from_utf8_lossy(&self.bytes);
+        let text = text.trim();
+        if self.dropped == 0 {
+            text.to_string()
+        } else {
+            format!("{}...[truncated {} bytes]", text, self.dropped)
         }
-        Err(AppError::ActionExec {
-            command: command_to_run,
-            source: std::io::Error::new(std::io::ErrorKind::Other, "Command failed"),
+    }
+}
+
+async fn capture(
+    reader: Option<impl AsyncRead + Unpin>,
+    limit: usize,
+) -> std::io::Result<CapturedOutput> {
+    let mut output = CapturedOutput::default();
+    let Some(mut reader) = reader else {
+        return Ok(output);
+    };
+    let mut buf = [0u8; 8192];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(output);
+        }
+        let kept = read.min(limit.saturating_sub(output.bytes.len()));
+        output.bytes.extend_from_slice(&buf[..kept]);
+        output.dropped += read - kept;
+    }
+}
+
+// `code` is None when the process was terminated by a signal.
+fn report_output(
+    config: &Config,
+    command_to_run: &str,
+    code: Option<i32>,
+    stdout: &CapturedOutput,
+    stderr: &CapturedOutput,
+) -> Result<()> {
+    let success = code == Some(0);
+    let log_output = match config.log_action_output {
+        LogActionOutput::Always => true,
+        LogActionOutput::OnError => !success,
+        LogActionOutput::Never => false,
+    };
+    if log_output {
+        let stdout = stdout.render();
+        if !stdout.is_empty() {
+            debug!(stdout = %stdout, "Command stdout output");
+        }
+        let stderr = stderr.render();
+        if !stderr.is_empty() {
+            debug!(stderr = %stderr, "Command stderr output");
+        }
+    }
+    if success {
+        debug!("Command executed successfully");
+        Ok(())
+    } else {
+        let reason = match code {
+            Some(126) => "Command failed (exit code 126): command is not executable".to_string(),
+            Some(127) => {
+                "Command failed (exit code 127): command not found, is it on PATH?".to_string()
+            }
+            Some(code) => format!("Command failed (exit code {})", code),
+            None => "Command terminated by signal".to_string(),
+        };
+        Err(AppError::CommandFailed {
+            command: command_to_run.to_string(),
+            code,
+            reason,
         })
     }
 }
+
+fn write_temp_script(contents: &str, executable: bool) -> Result<TempPath> {
+    let suffix = if cfg!(target_os = "windows") {
+        ".cmd"
+    } else {
+        ".sh"
+    };
+    let mut file = tempfile::Builder::new()
+        .prefix("kadesh-")
+        .suffix(suffix)
+        .tempfile()?;
+    file.write_all(contents.as_bytes())?;
+    file.flush()?;
+    #[cfg(unix)]
+    if executable {
+        use std::os::unix::fs::PermissionsExt;
+        file.as_file()
+            .set_permissions(std::fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(not(unix))]
+    let _ = executable;
+    Ok(file.into_temp_path())
+}
+
+fn write_manifest(paths: &[PathBuf], policy: NonUtf8Policy) -> Result<TempPath> {
+    let mut file = tempfile::Builder::new()
+        .prefix("kadesh-paths-")
+        .suffix(".txt")
+        .tempfile()?;
+    for path in paths {
+        writeln!(file, "{}", path_text(path, policy)?)?;
+    }
+    file.flush()?;
+    Ok(file.into_temp_path())
+}
+
+fn budget_exhausted(command: &str, attempts: u32, budget: Duration) -> AppError {
+    AppError::ActionBudgetExhausted {
+        command: command.to_string(),
+        attempts,
+        budget_ms: budget.as_millis(),
+    }
+}
+
+// Placeholders are expanded in one pass over the template, so text that a
+// value brings in is never expanded again. Braces around anything else are
+// left as they are.
+fn render_template(
+    template: &str,
+    path_str: &str,
+    context: &ActionContext,
+    policy: NonUtf8Policy,
+) -> Result<String> {
+    let optional_path = |p: &Option<PathBuf>| -> Result<String> {
+        match p {
+            Some(p) => Ok(path_text(p, policy)?.into_owned()),
+            None => Ok(String::new()),
+        }
+    };
+    let path = Path::new(path_str);
+    let value = |key: &str| -> Result<Option<String>> {
+        let value = match key {
+            "" => path_str.to_string(),
+            "rename_from" => optional_path(&context.rename_from)?,
+            "rename_to" => optional_path(&context.rename_to)?,
+            "old_target" => optional_path(&context.old_target)?,
+            "new_target" => optional_path(&context.new_target)?,
+            "watch_index" => context.watch_index.to_string(),
+            "watch" => context.watch_name.clone(),
+            "timestamp" => {
+                humantime::format_rfc3339_millis(context.event_time.unwrap_or_else(SystemTime::now))
+                    .to_string()
+            }
+            "paths" => context
+                .paths
+                .iter()
+                .map(|p| path_text(p, policy).map(|p| pool::quote(&p)))
+                .collect::<Result<Vec<_>>>()?
+                .join(" "),
+            "line" => pool::quote(context.line.as_deref().unwrap_or_default()),
+            "capture" => pool::quote(context.capture.as_deref().unwrap_or_default()),
+            "error" => pool::quote(context.error.as_deref().unwrap_or_default()),
+            "exit_code" => context
+                .exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+            "attempt" => context.attempt.to_string(),
+            "invocation_id" => context.invocation_id.clone(),
+            "name" => path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            "relative" => context
+                .watch_root
+                .as_deref()
+                .and_then(|root| path.strip_prefix(root).ok())
+                .and_then(Path::to_str)
+                .unwrap_or(path_str)
+                .to_string(),
+            // Segment names never clash with the built-in placeholders.
+            segment => match context.segments.iter().find(|(name, _)| name == segment) {
+                Some((_, value)) => value.clone(),
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(value))
+    };
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let expanded = match after.find('}') {
+            Some(close) => value(&after[..close])?.map(|value| (value, close)),
+            None => None,
+        };
+        match expanded {
+            Some((value, close)) => {
+                rendered.push_str(&value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// Paths substituted into commands must be text. With `lossy`, invalid bytes
+// become U+FFFD, so the command sees a path that does not exist on disk.
+fn path_text(path: &Path, policy: NonUtf8Policy) -> Result<Cow<'_, str>> {
+    match path.to_str() {
+        Some(text) => Ok(Cow::Borrowed(text)),
+        None if policy == NonUtf8Policy::Lossy => {
+            warn!(path = ?path, "Path is not valid UTF-8, substituting it lossily");
+            Ok(path.to_string_lossy())
+        }
+        None => Err(AppError::PathNonUtf8(path.to_path_buf())),
+    }
+}
+
+fn path_to_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| AppError::PathNonUtf8(path.to_path_buf()))
+}
+
+fn resolve_target<'a>(action: &Action, path: &'a Path) -> (&'a Path, Option<&'a Path>) {
+    if !action.in_place {
+        return (path, None);
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            (Path::new(name), Some(parent))
+        }
+        _ => (path, None),
+    }
+}
+
+fn spawn_error(command: &str, source: std::io::Error) -> AppError {
+    let hint = match source.kind() {
+        ErrorKind::NotFound => "shell interpreter not found, is it on PATH?",
+        ErrorKind::PermissionDenied => "permission denied, is the interpreter executable?",
+        _ => "the process could not be spawned",
+    };
+    AppError::ActionSpawn {
+        command: command.to_string(),
+        hint,
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    async fn run(config: &Config, context: &ActionContext) -> Result<()> {
+        let watch = &config.watches[0];
+        execute_action(
+            config,
+            watch,
+            &watch.actions[0],
+            Path::new("/srv/in/report.txt"),
+            context,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn watch_env_reaches_the_command_and_action_env_wins() {
+        let config = config(
+            r#"
+            [[watch]]
+            path = "/srv/in"
+            env = { NODE_ENV = "production", TIER = "watch", HOME = "/watch-home", FILE = "{name}" }
+            [[watch.actions]]
+            event = "any"
+            command = 'test "$NODE_ENV" = production && test "$TIER" = action && test "$HOME" = /watch-home && test "$FILE" = report.txt'
+            env = { TIER = "action" }
+            "#,
+        );
+        run(&config, &ActionContext::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn command_fails_when_the_variable_differs() {
+        let config = config(
+            r#"
+            [[watch]]
+            path = "/srv/in"
+            env = { NODE_ENV = "production" }
+            [[watch.actions]]
+            event = "any"
+            command = 'test "$NODE_ENV" = staging'
+            "#,
+        );
+        assert!(run(&config, &ActionContext::default()).await.is_err());
+    }
+
+    fn render(template: &str, context: &ActionContext) -> String {
+        render_template(
+            template,
+            "/srv/in/report.txt",
+            context,
+            NonUtf8Policy::Error,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn line_is_quoted_for_the_shell() {
+        let context = ActionContext {
+            line: Some("x'; rm -rf / #".into()),
+            ..ActionContext::default()
+        };
+        assert_eq!(render("echo {line}", &context), r"echo 'x'\''; rm -rf / #'");
+    }
+
+    #[test]
+    fn error_is_quoted_for_the_shell() {
+        let context = ActionContext {
+            error: Some("Failed to run command 'cp {} /out': exit 1; touch /tmp/x".into()),
+            ..ActionContext::default()
+        };
+        assert_eq!(
+            render("logger failed: {error}", &context),
+            r"logger failed: 'Failed to run command '\''cp {} /out'\'': exit 1; touch /tmp/x'"
+        );
+    }
+
+    #[tokio::test]
+    async fn captured_output_reaches_then_as_one_quoted_word() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("captured");
+        let config = config(&format!(
+            r#"
+            [[watch]]
+            path = "/srv/in"
+            [[watch.actions]]
+            event = "any"
+            command = '''echo "id: it's \$(touch {pwned}); done"'''
+            capture-regex = "id: (.*)"
+            then = {{ event = "any", command = "printf %s {{capture}} > {out}" }}
+            "#,
+            pwned = dir.path().join("pwned").display(),
+            out = out.display(),
+        ));
+        run(&config, &ActionContext::default()).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            format!("it's $(touch {}); done", dir.path().join("pwned").display())
+        );
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[test]
+    fn substituted_values_are_not_expanded_again() {
+        let context = ActionContext {
+            watch_name: "{name}".into(),
+            error: Some("{}".into()),
+            segments: vec![("tenant".into(), "{watch}".into())],
+            ..ActionContext::default()
+        };
+        assert_eq!(
+            render("{watch} {error} {tenant} {name}", &context),
+            "{name} '{}' {watch} report.txt"
+        );
+    }
+
+    #[test]
+    fn unknown_braces_are_left_alone() {
+        let context = ActionContext::default();
+        assert_eq!(
+            render("awk '{print $1}' {} {{}} {", &context),
+            "awk '{print $1}' /srv/in/report.txt {/srv/in/report.txt} {"
+        );
+    }
+
+    // An action that appends one line per attempt to the returned log, then
+    // runs `command`, with `settings` added to it.
+    fn attempts_log(dir: &Path, settings: &str, command: &str) -> (Config, PathBuf) {
+        let log = dir.join("attempts.log");
+        let config = config(&format!(
+            r#"
+            [[watch]]
+            path = "/srv/in"
+            [[watch.actions]]
+            event = "any"
+            {settings}
+            command = 'echo "$KADESH_ATTEMPT {{attempt}} $KADESH_INVOCATION_ID" >> {log}; {command}'
+            "#,
+            log = log.display(),
+        ));
+        (config, log)
+    }
+
+    #[tokio::test]
+    async fn retries_share_an_invocation_id_and_triggers_do_not() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, log) = attempts_log(dir.path(), "retries = 2", "exit 1");
+        assert!(run(&config, &ActionContext::default()).await.is_err());
+        assert!(run(&config, &ActionContext::default()).await.is_err());
+
+        let lines = std::fs::read_to_string(&log).unwrap();
+        let lines: Vec<Vec<&str>> = lines
+            .lines()
+            .map(|line| line.split(' ').collect())
+            .collect();
+        assert_eq!(lines.len(), 6);
+        for (n, line) in lines.iter().enumerate() {
+            let attempt = (n % 3).to_string();
+            assert_eq!(line[..2], [attempt.as_str(), attempt.as_str()]);
+        }
+        let ids: Vec<&str> = lines.iter().map(|line| line[2]).collect();
+        assert!(ids[..3].iter().all(|id| *id == ids[0]));
+        assert!(ids[3..].iter().all(|id| *id == ids[3]));
+        assert_ne!(ids[0], ids[3]);
+    }
+
+    #[tokio::test]
+    async fn only_listed_exit_codes_are_retried() {
+        let settings = "retries = 2\nretry-on-codes = [75]";
+        for (code, attempts) in [(75, 3), (1, 1)] {
+            let dir = tempfile::tempdir().unwrap();
+            let (config, log) = attempts_log(dir.path(), settings, &format!("exit {code}"));
+            assert!(run(&config, &ActionContext::default()).await.is_err());
+            let lines = std::fs::read_to_string(&log).unwrap().lines().count();
+            assert_eq!(lines, attempts, "exit {code}");
+        }
+    }
+}
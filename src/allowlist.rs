@@ -0,0 +1,77 @@
+//! `command-allowlist`: refuse to run commands whose program is not listed.
+//!
+//! This is defense in depth for privileged deployments, not a sandbox. Only
+//! the first word of the rendered command is checked, so commands that could
+//! start further programs through the shell (`;`, `&`, `|`, backticks, `$(`,
+//! newlines) are refused outright, as are `script` bodies. An allowed program
+//! can still do anything it is able to with its arguments, and `sh -c` style
+//! entries defeat the purpose entirely. Built-in copy/move/delete actions run
+//! no program and are not affected.
+
+use crate::config::{self, find_in_path};
+use crate::errors::{AppError, Result};
+use std::path::{Path, PathBuf};
+
+const SHELL_CHAINING: &[&str] = &[";", "&", "|", "`", "$(", "\n"];
+
+// One entry per line; blank lines and `#` comments are skipped.
+pub fn load(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path).map_err(|e| config::config_read_error(path, e))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+// Bare-name entries match a program invoked by that name, wherever PATH finds
+// it. Path entries match the invoked program after resolving symlinks, whether
+// it was written as a path or found on PATH.
+pub fn check(allowlist: &[String], command_line: &str, is_script: bool) -> Result<()> {
+    let refuse = |program: &str, reason: &str| AppError::CommandNotAllowed {
+        program: program.to_string(),
+        reason: reason.to_string(),
+    };
+    if is_script {
+        return Err(refuse("<script>", "script bodies cannot be checked"));
+    }
+    let program = command_line
+        .split_whitespace()
+        .find(|word| !is_assignment(word))
+        .unwrap_or_default();
+    if let Some(chain) = SHELL_CHAINING
+        .iter()
+        .find(|chain| command_line.contains(**chain))
+    {
+        return Err(refuse(
+            program,
+            &format!("'{}' could start unlisted programs", chain.escape_debug()),
+        ));
+    }
+    let resolved = if program.contains('/') {
+        std::fs::canonicalize(program).ok()
+    } else {
+        find_in_path(program).and_then(|found| std::fs::canonicalize(found).ok())
+    };
+    let allowed = allowlist.iter().any(|entry| {
+        if entry.contains('/') {
+            let entry = std::fs::canonicalize(entry).unwrap_or_else(|_| PathBuf::from(entry));
+            resolved.as_ref() == Some(&entry)
+        } else {
+            entry == program
+        }
+    });
+    if allowed {
+        Ok(())
+    } else {
+        Err(refuse(program, "program is not on the command allowlist"))
+    }
+}
+
+// Leading `NAME=value` words set the environment rather than naming the program.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use crate::watcher::FsWatcher;
+use notify::RecursiveMode;
+use notify_debouncer_full::DebounceEventResult;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::info;
+
+pub struct BenchOptions {
+    pub dir: PathBuf,
+    pub rate: u32,
+    pub duration: Duration,
+    pub files: usize,
+}
+
+// Time of the latest write to each generated file, so an event can be matched
+// back to the operation that caused it.
+type LastWrites = Arc<Mutex<HashMap<PathBuf, Instant>>>;
+
+// Churns files in `dir` through the same debouncer settings the config would use
+// and measures how long each change takes to come out as an event.
+pub async fn run(config: &Config, options: BenchOptions) -> Result<()> {
+    tokio::fs::create_dir_all(&options.dir).await?;
+    let dir = options.dir.canonicalize()?;
+
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<DebounceEventResult>();
+    let mut watcher = FsWatcher::new(config, move |result| {
+        let _ = event_tx.send(result);
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(AppError::Notify)?;
+
+    let last_writes: LastWrites = Arc::default();
+    let generator = tokio::spawn(churn(
+        dir.clone(),
+        options.rate.max(1),
+        options.duration,
+        options.files.max(1),
+        Arc::clone(&last_writes),
+    ));
+
+    // Leave time for the last changes to clear the debouncer.
+    let grace = Duration::from_millis(config.debounce_ms * 2 + 500);
+    let deadline = tokio::time::Instant::now() + options.duration + grace;
+    let started = Instant::now();
+    let mut latencies = Vec::new();
+    let mut events = 0usize;
+    while let Ok(Some(result)) = tokio::time::timeout_at(deadline, event_rx.recv()).await {
+        let Ok(batch) = result else {
+            continue;
+        };
+        let now = Instant::now();
+        let writes = last_writes.lock().unwrap_or_else(PoisonError::into_inner);
+        for event in batch {
+            events += 1;
+            if let Some(written) = event.paths.first().and_then(|path| writes.get(path)) {
+                latencies.push(now.saturating_duration_since(*written));
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+    drop(watcher);
+
+    let operations = generator
+        .await
+        .map_err(|e| AppError::Io(std::io::Error::other(e)))??;
+    cleanup(&dir, options.files).await;
+
+    latencies.sort();
+    println!("operations: {}", operations);
+    println!(
+        "events:     {} ({:.1}/s)",
+        events,
+        events as f64 / elapsed.as_secs_f64()
+    );
+    println!("latency (write to debounced event):");
+    for (label, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("max", 1.0)] {
+        match percentile(&latencies, quantile) {
+            Some(latency) => println!("  {}: {}ms", label, latency.as_millis()),
+            None => println!("  {}: n/a", label),
+        }
+    }
+    Ok(())
+}
+
+async fn churn(
+    dir: PathBuf,
+    rate: u32,
+    duration: Duration,
+    files: usize,
+    last_writes: LastWrites,
+) -> Result<usize> {
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / rate);
+    let started = Instant::now();
+    let mut operations = 0;
+    while started.elapsed() < duration {
+        interval.tick().await;
+        let path = bench_file(&dir, operations % files);
+        // Cycle each file through create, modify and delete.
+        match (operations / files) % 3 {
+            0 => tokio::fs::write(&path, b"kadesh bench\n").await?,
+            1 => tokio::fs::write(&path, operations.to_string()).await?,
+            _ => tokio::fs::remove_file(&path).await?,
+        }
+        last_writes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path, Instant::now());
+        operations += 1;
+    }
+    info!(operations, "Load generation finished");
+    Ok(operations)
+}
+
+async fn cleanup(dir: &Path, files: usize) {
+    for index in 0..files {
+        let _ = tokio::fs::remove_file(bench_file(dir, index)).await;
+    }
+}
+
+fn bench_file(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("kadesh-bench-{}.tmp", index))
+}
+
+fn percentile(sorted: &[Duration], quantile: f64) -> Option<Duration> {
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(last as f64 * quantile).round() as usize])
+}
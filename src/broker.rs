@@ -0,0 +1,154 @@
+//! Publishes matched events to a Redis channel with `PUBLISH`, for consumers in
+//! other services. Built only with the `broker` feature.
+//!
+//! Events are handed to a bounded queue and sent by a background task, so a
+//! slow or unreachable server never holds up event processing. While the
+//! server is down the queue fills up and further events are dropped; the task
+//! reconnects with a capped backoff and the event being sent when a connection
+//! broke is retried on the next one, so a consumer may rarely see it twice.
+//! Redis does not keep messages for absent subscribers either way.
+
+use crate::config::{BrokerConfig, WatchConfig};
+use notify::EventKind;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+static QUEUE: OnceLock<mpsc::Sender<String>> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// Same shape as dead-letter records, with all of the event's paths that fell
+// under the watch.
+#[derive(Serialize)]
+struct PublishedEvent<'a> {
+    timestamp: String,
+    watch_index: usize,
+    watch: &'a str,
+    event: EventKind,
+    paths: &'a [PathBuf],
+}
+
+pub fn spawn(broker: &BrokerConfig) {
+    let (tx, rx) = mpsc::channel(broker.buffer.max(1));
+    if QUEUE.set(tx).is_err() {
+        return;
+    }
+    tokio::spawn(run(broker.clone(), rx));
+}
+
+pub fn publish(watch_index: usize, watch: &WatchConfig, kind: EventKind, paths: &[PathBuf]) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let record = PublishedEvent {
+        timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        watch_index,
+        watch: watch.display_name(),
+        event: kind,
+        paths,
+    };
+    let payload = match serde_json::to_string(&record) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!(error = %e, "Failed to serialize event for the broker");
+            return;
+        }
+    };
+    if queue.try_send(payload).is_err() {
+        // Warn on the first drop and then every 1000th, not for each event.
+        let dropped = DROPPED.fetch_add(1, Ordering::Relaxed);
+        if dropped.is_multiple_of(1000) {
+            warn!(
+                dropped = dropped + 1,
+                "Broker queue is full, dropping events"
+            );
+        }
+    }
+}
+
+async fn run(broker: BrokerConfig, mut rx: mpsc::Receiver<String>) {
+    let mut backoff = Duration::from_millis(500);
+    let mut unsent: Option<String> = None;
+    loop {
+        let mut stream = match connect(&broker.address).await {
+            Ok(stream) => {
+                info!(address = %broker.address, channel = %broker.channel, "Connected to broker");
+                backoff = Duration::from_millis(500);
+                stream
+            }
+            Err(e) => {
+                warn!(address = %broker.address, error = %e, retry_in = ?backoff, "Failed to connect to broker");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        loop {
+            let payload = match unsent.take() {
+                Some(payload) => payload,
+                None => match rx.recv().await {
+                    Some(payload) => payload,
+                    None => return,
+                },
+            };
+            if let Err(e) = send(&mut stream, &broker.channel, &payload).await {
+                warn!(address = %broker.address, error = %e, "Lost connection to broker, reconnecting");
+                unsent = Some(payload);
+                break;
+            }
+        }
+    }
+}
+
+async fn connect(address: &str) -> std::io::Result<BufReader<TcpStream>> {
+    let stream = tokio::time::timeout(IO_TIMEOUT, TcpStream::connect(address)).await??;
+    Ok(BufReader::new(stream))
+}
+
+async fn send(
+    stream: &mut BufReader<TcpStream>,
+    channel: &str,
+    payload: &str,
+) -> std::io::Result<()> {
+    let command = encode(&["PUBLISH", channel, payload]);
+    tokio::time::timeout(IO_TIMEOUT, async {
+        stream.get_mut().write_all(&command).await?;
+        // The reply is the number of subscribers that got it, or an error.
+        let mut reply = String::new();
+        if stream.read_line(&mut reply).await? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "broker closed the connection",
+            ));
+        }
+        // An error reply leaves the connection usable, so the event is dropped
+        // rather than retried.
+        if let Some(error) = reply.strip_prefix('-') {
+            warn!(error = %error.trim_end(), "Broker rejected event");
+            return Ok(());
+        }
+        debug!(reply = %reply.trim_end(), "Published event");
+        Ok(())
+    })
+    .await?
+}
+
+// A RESP array of bulk strings, as Redis expects commands.
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend_from_slice(arg.as_bytes());
+        command.extend_from_slice(b"\r\n");
+    }
+    command
+}
@@ -0,0 +1,178 @@
+use crate::config::{ActionKind, ArchiveFormat};
+use crate::errors::{AppError, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+pub async fn run(
+    kind: ActionKind,
+    source: &Path,
+    destination: Option<&Path>,
+    overwrite: bool,
+    archive_format: ArchiveFormat,
+    delete_original: bool,
+) -> Result<()> {
+    let io_error = |e| AppError::BuiltinAction {
+        action: kind.as_str(),
+        path: source.to_path_buf(),
+        source: e,
+    };
+
+    match (kind, destination) {
+        (ActionKind::Delete, _) => {
+            let metadata = tokio::fs::symlink_metadata(source)
+                .await
+                .map_err(io_error)?;
+            if metadata.is_dir() {
+                tokio::fs::remove_dir(source).await.map_err(io_error)?;
+            } else {
+                tokio::fs::remove_file(source).await.map_err(io_error)?;
+            }
+        }
+        (ActionKind::Copy, Some(destination)) => {
+            prepare_destination(destination, overwrite).await?;
+            tokio::fs::copy(source, destination)
+                .await
+                .map_err(io_error)?;
+        }
+        (ActionKind::Move, Some(destination)) => {
+            prepare_destination(destination, overwrite).await?;
+            match tokio::fs::rename(source, destination).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                    tokio::fs::copy(source, destination)
+                        .await
+                        .map_err(io_error)?;
+                    tokio::fs::remove_file(source).await.map_err(io_error)?;
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        (ActionKind::Compress, destination) => {
+            let destination = destination
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| archive_path(source, archive_format));
+            prepare_destination(&destination, overwrite).await?;
+            if !compress(source, destination, overwrite, archive_format)
+                .await
+                .map_err(io_error)?
+            {
+                debug!("File is gone, nothing to compress");
+                return Ok(());
+            }
+            if delete_original {
+                match tokio::fs::remove_file(source).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(io_error(e)),
+                }
+            }
+        }
+        (ActionKind::Copy | ActionKind::Move, None) | (ActionKind::Command, _) => {
+            unreachable!("built-in actions are validated at config load")
+        }
+    }
+
+    debug!("Built-in action completed");
+    Ok(())
+}
+
+fn archive_path(source: &Path, format: ArchiveFormat) -> PathBuf {
+    let mut path = source.as_os_str().to_owned();
+    path.push(".");
+    path.push(format.extension());
+    PathBuf::from(path)
+}
+
+// Returns false if the file was gone before it could be opened. Once open, it
+// is read to the end even if it is removed meanwhile. The archive is written
+// to a temporary file next to the destination and only then moved into place,
+// so a failure never leaves a truncated archive behind.
+#[cfg(feature = "compress")]
+async fn compress(
+    source: &Path,
+    destination: PathBuf,
+    overwrite: bool,
+    format: ArchiveFormat,
+) -> std::io::Result<bool> {
+    use std::io::Write;
+
+    let source = source.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut input = match std::fs::File::open(&source) {
+            Ok(input) => input,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if !input.metadata()?.is_file() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "only regular files can be compressed",
+            ));
+        }
+        let dir = match destination.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut archive = tempfile::NamedTempFile::new_in(dir)?;
+        let name = source
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match format {
+            ArchiveFormat::Gzip => {
+                let mut encoder = flate2::GzBuilder::new()
+                    .filename(name)
+                    .write(archive.as_file_mut(), flate2::Compression::default());
+                std::io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?.flush()?;
+            }
+            ArchiveFormat::Zip => {
+                let mut zip = zip::ZipWriter::new(archive.as_file_mut());
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated);
+                zip.start_file(name, options)
+                    .map_err(std::io::Error::other)?;
+                std::io::copy(&mut input, &mut zip)?;
+                zip.finish().map_err(std::io::Error::other)?;
+            }
+        }
+        let persisted = if overwrite {
+            archive.persist(&destination)
+        } else {
+            archive.persist_noclobber(&destination)
+        };
+        persisted.map_err(|e| e.error)?;
+        Ok(true)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+#[cfg(not(feature = "compress"))]
+async fn compress(
+    _source: &Path,
+    _destination: PathBuf,
+    _overwrite: bool,
+    _format: ArchiveFormat,
+) -> std::io::Result<bool> {
+    unreachable!("compress actions are rejected at config load without the feature")
+}
+
+async fn prepare_destination(destination: &Path, overwrite: bool) -> Result<()> {
+    if !overwrite && tokio::fs::try_exists(destination).await.unwrap_or(false) {
+        return Err(AppError::DestinationExists(destination.to_path_buf()));
+    }
+    if let Some(parent) = destination.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| AppError::BuiltinAction {
+                action: "create directory",
+                path: parent.to_path_buf(),
+                source: e,
+            })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use std::time::{Instant, SystemTime};
+
+// `Instant`s have no calendar meaning, so an event's time is projected back
+// from the current wall clock by however long ago it was recorded.
+pub fn wall_clock(at: Instant) -> SystemTime {
+    let now = SystemTime::now();
+    now.checked_sub(at.elapsed()).unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn projects_an_instant_back_from_now() {
+        let at = Instant::now() - Duration::from_secs(60);
+        let ago = SystemTime::now().duration_since(wall_clock(at)).unwrap();
+        assert!(ago >= Duration::from_secs(60) && ago < Duration::from_secs(61));
+    }
+}
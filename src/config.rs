@@ -1,65 +1,1016 @@
+use crate::control;
 use crate::errors::{AppError, Result};
+use crate::filter_expr::FilterExpr;
+use crate::ownership::{self, GroupId, UserId};
 use notify::EventKind;
-use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
-use serde::Deserialize;
+use notify::event::{
+    AccessKind, AccessMode, CreateKind, DataChange, MetadataKind, ModifyKind, RemoveKind,
+    RenameMode,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, HashMap, HashSet},
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
 };
-use tracing::warn;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, warn};
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     #[serde(default = "default_log_level")]
     pub log_level: String,
+    /// Named log files that watches can send their action logs to with
+    /// `log-target` (see `log_targets.rs`). Read once at startup.
+    #[serde(default)]
+    pub log_targets: HashMap<String, PathBuf>,
+    /// How long events are collected and coalesced before processing. 0 turns
+    /// the debouncer off and hands on every raw event at once: lower latency,
+    /// but a single save can then show up as several create/modify/close
+    /// events, renames arrive as separate from/to halves besides the paired
+    /// one, and create+remove pairs of temp files are no longer dropped.
     #[serde(default = "default_debounce_ms")]
     pub debounce_ms: u64,
+    /// Coalesce events per group on top of the per-path debounce. With
+    /// `first_component`, events under each top-level entry of a watch root
+    /// (each package of a monorepo, say) are held until that entry has been
+    /// quiet for `debounce-ms` and then released together, independently of
+    /// activity elsewhere. Every group with a burst in progress keeps its events
+    /// in memory, so a root with many busy top-level entries holds that many
+    /// bursts at once; a group is released early once it has held 1000
+    /// distinct events or for ten windows.
+    #[serde(default)]
+    pub debounce_group: DebounceGroup,
+    /// Share (0.0 to 1.0) of events whose per-event debug lines in event
+    /// processing are logged, to keep busy watches readable.
+    #[serde(default = "default_log_sample_rate")]
+    pub log_sample_rate: f64,
+    /// Log a "still watching" line at this interval, to show kadesh is alive
+    /// through quiet periods.
+    #[serde(default)]
+    pub heartbeat_interval_ms: Option<u64>,
+    /// Keep the last this many events, with the actions they started and how
+    /// those ended, for the control endpoint's `GET /history`. 0 keeps none.
+    #[serde(default)]
+    pub history_size: usize,
+    /// Track file identities in the debouncer for better rename correlation,
+    /// at the cost of memory proportional to the number of watched files.
+    #[serde(default)]
+    pub file_id_cache: bool,
+    #[serde(default)]
+    pub dedup_window_ms: u64,
+    /// Drop files that are created and removed again within
+    /// `transient-window-ms`, such as editor and build tool temp files, along
+    /// with all their events. Events for newly created files are held for that
+    /// long first, so actions on them start correspondingly later.
+    #[serde(default)]
+    pub ignore_transient: bool,
+    #[serde(default = "default_transient_window_ms")]
+    pub transient_window_ms: u64,
+    /// Events received within this many ms of startup are discarded.
+    #[serde(default)]
+    pub startup_settle_ms: u64,
+    /// Extra per-category (`create`, `modify`, `remove`) debounce windows in ms,
+    /// applied after the global `debounce-ms`. Events in a listed category are held
+    /// until no further event for the same paths arrives within its window, which
+    /// adds that much latency; set `debounce-ms` to the smallest window you need.
+    #[serde(default)]
+    pub debounce_by_kind: HashMap<String, u64>,
+    #[serde(default)]
+    pub log_action_output: LogActionOutput,
+    /// Caps how much of each stream of a command's output is kept for logging;
+    /// the rest is dropped with a `...[truncated N bytes]` marker.
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+    /// What to do when a path substituted into an action is not valid UTF-8:
+    /// `error` fails the action, `skip` skips it quietly, and `lossy` replaces
+    /// the invalid bytes. Lossy paths don't name the real file, so commands
+    /// that open them will fail or, worse, hit a different file. Built-in
+    /// copy/move/delete actions act on the raw path; only their `destination`
+    /// template goes through this.
+    #[serde(default)]
+    pub on_non_utf8: NonUtf8Policy,
+    /// Octal umask for command actions, e.g. `"022"`. Unix only; an invalid
+    /// value is logged and the inherited umask is kept.
+    #[serde(default)]
+    pub umask: Option<String>,
+    /// Programs command actions may run; anything else fails with an error.
+    /// See `allowlist.rs` for what is and isn't checked. `--command-allowlist`
+    /// replaces this with a list from a file the config can't change.
+    #[serde(default)]
+    pub command_allowlist: Option<Vec<String>>,
+    /// Inherited environment variables actions may see, as globs like `"LC_*"`.
+    /// Unset passes everything. Variables kadesh sets itself and `env` entries
+    /// are always passed.
+    #[serde(default)]
+    pub env_allow: Option<Vec<GlobPattern>>,
+    /// Inherited environment variables kept from actions, e.g. `"AWS_SECRET*"`.
+    /// Applied after `env-allow`.
+    #[serde(default)]
+    pub env_deny: Vec<GlobPattern>,
+    /// Run command actions as this user (name or uid), in its primary group
+    /// unless `run-as-group` is set. Unix only, and kadesh must be started as
+    /// root. If privileges cannot be dropped the action fails; it never runs as
+    /// kadesh's own user instead. Such actions always spawn, bypassing the pool.
+    #[serde(default)]
+    pub run_as_user: Option<UserId>,
+    #[serde(default)]
+    pub run_as_group: Option<GroupId>,
+    /// Shut down with an error on the first failed action. Useful for one-shot
+    /// runs; in daemon mode a single failure would stop all watching.
+    #[serde(default)]
+    pub fail_fast: bool,
+    /// Dispatch is paused while this file exists. SIGUSR1 also toggles pausing
+    /// on Unix.
+    #[serde(default)]
+    pub pause_file: Option<PathBuf>,
+    /// What happens to events received while paused.
+    #[serde(default)]
+    pub on_pause: PausePolicy,
+    /// Start at most this many actions per second across all watches. This
+    /// bounds the rate, not how many run at once. Digests are not counted.
+    #[serde(default)]
+    pub max_actions_per_second: Option<u32>,
+    /// What happens to actions over the rate: `wait` delays them until a slot
+    /// frees up, `drop` discards them.
+    #[serde(default)]
+    pub on_rate_limit: RateLimitPolicy,
+    /// What happens when the kernel's event queue overflowed, or kadesh fell
+    /// so far behind that it dropped debounced events, and events may have
+    /// been lost.
+    #[serde(default)]
+    pub on_overflow: OverflowPolicy,
+    /// Actions that still fail after all retries are appended here as JSON lines,
+    /// for review or `kadesh replay`.
+    #[serde(default)]
+    pub dead_letter_file: Option<PathBuf>,
+    /// Serve `POST /actions/<name>` here to run named actions on demand (see
+    /// `control.rs`). A bare port binds to 127.0.0.1.
+    #[serde(default)]
+    pub control_addr: Option<String>,
+    /// Bearer token required by the control endpoint.
+    #[serde(default)]
+    pub control_token: Option<String>,
+    /// Publish every event a watch accepts to a message broker. Needs a build
+    /// with the `broker` feature.
+    #[serde(default)]
+    pub broker: Option<BrokerConfig>,
+    #[serde(default)]
+    pub exit_on: Option<ExitOn>,
+    /// Run once after the watches are set up, before any event is processed,
+    /// e.g. to warm a cache or announce that monitoring started. Events that
+    /// arrive meanwhile wait, so keep it short. Like `on-stop`, it is a command
+    /// action run on an empty path with the first watch's `env`; its `event`
+    /// is not consulted, and a failure is logged without stopping kadesh.
+    #[serde(default)]
+    pub on_start: Option<Action>,
+    /// Run once on a graceful shutdown (Ctrl+C or `exit-on`), after pending
+    /// digests. It is given 10 seconds and then abandoned.
+    #[serde(default)]
+    pub on_stop: Option<Action>,
+    #[serde(default = "default_processor_restart_limit")]
+    pub processor_restart_limit: u32,
+    /// Upper bound on the number of directories watched across all entries. Once
+    /// a watch would push past it, that watch and all later ones are skipped.
+    #[serde(default)]
+    pub max_watches: Option<usize>,
+    /// Interpreter and arguments used to run commands, e.g. `"bash -c"`. Defaults
+    /// to `sh -c` (`cmd /C` on Windows). Pooled workers always use `sh`.
+    #[serde(default)]
+    pub shell: Option<String>,
+    /// `spawn` starts a fresh shell per command; `pool` reuses long-lived shells
+    /// (see `pool.rs` for the caveats). Ignored on Windows.
+    #[serde(default)]
+    pub exec_mode: ExecMode,
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
     #[serde(rename = "watch", default)]
     pub watches: Vec<WatchConfig>,
+    #[serde(rename = "action", default)]
+    pub named_actions: HashMap<String, Action>,
+    #[serde(rename = "route", default)]
+    pub routes: Vec<Route>,
+    #[serde(default)]
+    pub route_match: RouteMatch,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct BrokerConfig {
+    /// `host:port` of a Redis server; events are sent with `PUBLISH`.
+    pub address: String,
+    pub channel: String,
+    /// Events held while the broker is slow or unreachable; beyond this, new
+    /// events are dropped.
+    #[serde(default = "default_broker_buffer")]
+    pub buffer: usize,
+}
+
+// Stops kadesh once an event matches, e.g. to wait for a flag file in one-shot
+// runs. The actions that event starts are run to completion first.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExitOn {
+    #[serde(default = "default_exit_on_event")]
+    pub event: String,
+    /// Glob over the watch-relative path, e.g. `"done.flag"`. Unset matches
+    /// any path.
+    #[serde(default)]
+    pub path: Option<GlobPattern>,
+    /// Exit status to stop with.
+    #[serde(default)]
+    pub code: i32,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+// Routes map "what happened" to a named `[action.<name>]`, and are evaluated in
+// order for every event a watch accepts, after the watch's own actions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Route {
+    pub when: String,
+    #[serde(default)]
+    pub if_path: Option<GlobPattern>,
+    pub run: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteMatch {
+    #[default]
+    First,
+    All,
+}
+
+// A glob matched against the watch-relative path of an event, or against a
+// variable name for `env-allow` and `env-deny`.
+#[derive(Debug, Clone)]
+pub struct GlobPattern(glob::Pattern);
+
+impl GlobPattern {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn matches(&self, path: &Path, watch_root: &Path) -> bool {
+        self.0
+            .matches_path(path.strip_prefix(watch_root).unwrap_or(path))
+    }
+
+    // Like `matches`, but `*` does not cross a `/`, so the glob describes the
+    // path level by level.
+    pub fn matches_shape(&self, path: &Path, watch_root: &Path) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        self.0
+            .matches_path_with(path.strip_prefix(watch_root).unwrap_or(path), options)
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.0.matches(name)
+    }
+}
+
+impl Serialize for GlobPattern {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for GlobPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        glob::Pattern::new(&source)
+            .map(GlobPattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid glob '{}': {}", source, e)))
+    }
+}
+
+// Placeholders actions already fill in, which a `path-template` segment may not
+// shadow.
+const RESERVED_PLACEHOLDERS: &[&str] = &[
+    "rename_from",
+    "rename_to",
+    "old_target",
+    "new_target",
+    "watch_index",
+    "watch",
+    "timestamp",
+    "paths",
+    "line",
+    "capture",
+    "error",
+    "exit_code",
+    "attempt",
+    "invocation_id",
+    "name",
+    "relative",
+];
+
+// A `path-template`: a glob over the watch-relative path in which whole levels
+// written as `{name}` are captured. Captures must come before any `**`, so each
+// one sits at a fixed depth.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    source: String,
+    glob: GlobPattern,
+    segments: Vec<(usize, String)>,
+}
+
+impl PathTemplate {
+    pub fn parse(source: &str) -> std::result::Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut after_globstar = false;
+        let mut levels = Vec::new();
+        for (depth, level) in source.split('/').enumerate() {
+            after_globstar |= level == "**";
+            let Some(name) = level
+                .strip_prefix('{')
+                .and_then(|level| level.strip_suffix('}'))
+            else {
+                levels.push(level);
+                continue;
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("'{}' is not a valid segment name", level));
+            }
+            if RESERVED_PLACEHOLDERS.contains(&name) {
+                return Err(format!("'{}' is already a placeholder", level));
+            }
+            if segments.iter().any(|(_, seen)| seen == name) {
+                return Err(format!("'{}' is captured twice", level));
+            }
+            if after_globstar {
+                return Err(format!("'{}' comes after '**'", level));
+            }
+            segments.push((depth, name.to_string()));
+            levels.push("*");
+        }
+        let glob = glob::Pattern::new(&levels.join("/")).map_err(|e| e.to_string())?;
+        Ok(PathTemplate {
+            source: source.to_string(),
+            glob: GlobPattern(glob),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches_shape(&self, path: &Path, watch_root: &Path) -> bool {
+        self.glob.matches_shape(path, watch_root)
+    }
+
+    // The captured segments as (name, value), or None if the path has another
+    // shape. Levels that aren't valid UTF-8 are captured lossily.
+    pub fn captures(&self, path: &Path, watch_root: &Path) -> Option<Vec<(String, String)>> {
+        if !self.matches_shape(path, watch_root) {
+            return None;
+        }
+        let levels: Vec<_> = path
+            .strip_prefix(watch_root)
+            .unwrap_or(path)
+            .components()
+            .collect();
+        self.segments
+            .iter()
+            .map(|(depth, name)| {
+                let level = levels.get(*depth)?.as_os_str().to_string_lossy();
+                Some((name.clone(), level.into_owned()))
+            })
+            .collect()
+    }
+}
+
+impl Serialize for PathTemplate {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for PathTemplate {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        PathTemplate::parse(&source).map_err(|e| {
+            serde::de::Error::custom(format!("invalid path-template '{}': {}", source, e))
+        })
+    }
+}
+
+// A duration written for people, e.g. `90s`, `1h 30m` or `2days`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanDuration(pub Duration);
+
+impl std::fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        humantime::format_duration(self.0).fmt(f)
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        humantime::parse_duration(&source)
+            .map(HumanDuration)
+            .map_err(|e| serde::de::Error::custom(format!("invalid duration '{}': {}", source, e)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgeSource {
+    /// Last content change; kept by copies that preserve timestamps and
+    /// settable with `touch`.
+    #[default]
+    Mtime,
+    /// Last content or metadata change, including chmod, chown and renames.
+    /// Falls back to mtime where the platform has no ctime.
+    Ctime,
+}
+
+impl AgeSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgeSource::Mtime => "mtime",
+            AgeSource::Ctime => "ctime",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitPolicy {
+    #[default]
+    Wait,
+    Drop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PausePolicy {
+    #[default]
+    Drop,
+    Queue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DebounceGroup {
+    #[default]
+    Off,
+    FirstComponent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    Ignore,
+    #[default]
+    Warn,
+    /// Also treat every file under each watch as modified, so actions catch up
+    /// on whatever was missed. This runs them for unchanged files too.
+    Rescan,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NonUtf8Policy {
+    Skip,
+    Lossy,
+    #[default]
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecMode {
+    #[default]
+    Spawn,
+    Pool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogActionOutput {
+    Always,
+    #[default]
+    OnError,
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct WatchConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
     pub path: String,
+    /// Read the watched paths from this file instead, one per line; blank lines
+    /// and `#` comments are skipped. Each becomes a watch of its own with this
+    /// entry's settings. The file is re-read on reload (SIGHUP), so an action
+    /// on it can pick up changes: `command = "kill -HUP $PPID"`.
+    #[serde(default)]
+    pub paths_file: Option<PathBuf>,
+    /// Name of a `log-targets` entry that this watch's action logs go to
+    /// instead of stdout.
+    #[serde(default)]
+    pub log_target: Option<String>,
     #[serde(default)]
     pub recursive: bool,
+    /// The path is expected to not exist yet, so don't warn about it. Errors
+    /// other than a missing path, like permission problems, are still reported.
+    #[serde(default)]
+    pub allow_missing: bool,
+    /// Drop events on directories so actions only ever see files.
+    #[serde(default)]
+    pub files_only: bool,
+    /// Treat a file being closed after writing as the one signal that it
+    /// changed: content modify events and other access events are dropped, and
+    /// `modify` actions fire once on the close instead (`write_done` matches
+    /// only the close). Relies on inotify's IN_CLOSE_WRITE, so it is Linux only.
+    /// Writers that keep a file open, such as loggers and databases, do not
+    /// close it after each write, so their changes go unseen; leave this off
+    /// for such files.
+    #[serde(default)]
+    pub prefer_close_write: bool,
+    /// Like `find -xdev`: drop events for paths on a different filesystem than
+    /// the root, such as mounts below it. notify still registers watches below
+    /// those mount points, so this only keeps their events from running actions.
+    #[serde(default)]
+    pub same_filesystem: bool,
+    /// Act on the first create of each path only; later creates of the same
+    /// path are ignored (see `once.rs`). Other events are not affected.
+    #[serde(default)]
+    pub once_per_path: bool,
+    /// Keep the seen paths in this file so they survive restarts.
+    #[serde(default)]
+    pub once_per_path_file: Option<PathBuf>,
+    /// Forget a seen path after this long, so it is acted on again.
+    #[serde(default)]
+    pub once_per_path_ttl_ms: Option<u64>,
+    /// Only act on paths of this shape, as a glob over the watch-relative path
+    /// such as `"packages/*/src/**"`. `*` stays within one directory level and
+    /// `**` spans any number of them. Everything below the root is still watched.
+    /// A whole level written as `{name}`, as in `"projects/{project}/**"`,
+    /// matches like `*` and hands that directory name to actions as the
+    /// `{project}` placeholder. Events on paths of another shape are ignored,
+    /// so actions never see a placeholder left unfilled.
+    #[serde(default)]
+    pub path_template: Option<PathTemplate>,
+    /// Ignore events on a path for this long after an action on it finishes,
+    /// so actions that touch their own file don't trigger themselves again.
+    /// The action's own events arrive after the debounce delay, so this needs
+    /// to be longer than `debounce-ms`.
+    #[serde(default)]
+    pub post_action_quiet_ms: Option<u64>,
+    /// Before acting on a file, wait until its size and mtime are unchanged
+    /// for this many polls in a row. Off (0) by default; useful for large
+    /// uploads that outlast the debounce. Files removed meanwhile are skipped.
+    /// Further events for a file being waited on start the count over, and the
+    /// actions run once, for the event that began the wait.
+    #[serde(default)]
+    pub stability_checks: u32,
+    #[serde(default = "default_stability_interval_ms")]
+    pub stability_interval_ms: u64,
+    /// Follow files like `tail -f`: each complete line appended to a file runs the
+    /// first matching action, with the line in `{line}` and `$KADESH_LINE`.
+    /// `{line}` is shell-quoted, so commands use it bare, as with `{paths}`. Lines
+    /// run concurrently like any other actions, so their order is not kept.
+    #[serde(default)]
+    pub tail: bool,
+    /// Treat `path` as a symlink and run `on-retarget` whenever it is repointed,
+    /// with `{old_target}` and `{new_target}` set. The link's directory is watched
+    /// rather than its target, and the link need not exist at startup.
+    #[serde(default)]
+    pub track_symlink: bool,
+    #[serde(default)]
+    pub on_retarget: Option<Action>,
+    /// Run when a directory under the watch loses its last entry, with the
+    /// directory as the path. Only changes seen through events count, so a
+    /// directory is first judged when something is created or removed in it.
+    #[serde(default)]
+    pub on_becomes_empty: Option<Action>,
+    /// Run when a directory under the watch gets its first entry.
+    #[serde(default)]
+    pub on_becomes_nonempty: Option<Action>,
+    /// Total size of the files under the watch above which `on-size-exceeded`
+    /// runs, once per crossing, with the root as the path. Each event walks the
+    /// whole tree to recompute it, so keep this to spool- or cache-sized trees.
+    #[serde(default)]
+    pub size_limit_bytes: Option<u64>,
+    #[serde(default)]
+    pub on_size_exceeded: Option<Action>,
+    /// Run when the total size drops back to the limit or below.
+    #[serde(default)]
+    pub on_size_below: Option<Action>,
     #[serde(default)]
     pub actions: Vec<Action>,
+    /// Actions keyed by file extension (`".scss"` or `"scss"`), chosen per path.
+    /// Paths with an unlisted extension, or whose entry's `event` does not match,
+    /// fall back to `actions`.
+    #[serde(default)]
+    pub by_extension: HashMap<String, Action>,
     #[serde(default)]
     pub filters: Filters,
+    #[serde(default)]
+    pub on_root_removed: RootRemovedPolicy,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(skip)]
+    resolved_root: OnceLock<PathBuf>,
+    #[serde(skip)]
+    id: OnceLock<u64>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RootRemovedPolicy {
+    #[default]
+    Log,
+    Reestablish,
+    Exit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Action {
     pub event: String,
-    pub command: String,
+    #[serde(default)]
+    pub kind: ActionKind,
+    /// Either one command, or a table of commands keyed by the event's primary
+    /// kind (`create`, `modify` or `remove`, with renames counting as
+    /// `modify`) plus an optional `default`. An event with no entry for its
+    /// kind and no `default` passes on to the next matching action.
+    #[serde(default)]
+    pub command: CommandSpec,
+    /// A multi-line alternative to `command`, run from a temporary file. Scripts
+    /// starting with a `#!` line run with that interpreter, others with `sh`.
+    /// Placeholders are substituted in the body as in `command`.
+    #[serde(default)]
+    pub script: Option<String>,
+    /// A guard run before the action, with the same placeholders and env. The
+    /// action only runs if it exits 0.
+    #[serde(default)]
+    pub when_command: Option<String>,
+    /// Target path for `copy`, `move` and `compress`, with the same placeholders
+    /// as `command`. `compress` defaults to the file's path plus `.gz` or `.zip`.
+    #[serde(default)]
+    pub destination: Option<String>,
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Archive written by `compress`.
+    #[serde(default)]
+    pub archive_format: ArchiveFormat,
+    /// Remove the file once `compress` has written its archive.
+    #[serde(default)]
+    pub delete_original: bool,
+    /// Run from the changed file's directory, with `{}` expanding to the bare file name.
+    #[serde(default)]
+    pub in_place: bool,
+    #[serde(default)]
+    pub retries: u32,
+    /// Only retry commands that exit with one of these codes, e.g. `[75]` for
+    /// EX_TEMPFAIL. Other failures, including ones without an exit code, are
+    /// final. Empty retries any failure.
+    #[serde(default)]
+    pub retry_on_codes: Vec<i32>,
+    /// Caps the time spent across the first attempt and all retries.
+    #[serde(default)]
+    pub max_total_duration_ms: Option<u64>,
+    /// Run on only the first of every N events that reach this action, across
+    /// all paths; the rest are dropped.
+    #[serde(default)]
+    pub sample_rate: Option<u64>,
+    /// Run at most once per this many ms for each path; events inside the
+    /// window are dropped, not deferred.
+    #[serde(default)]
+    pub throttle_ms: Option<u64>,
+    /// Collect matching paths for this long, then run once with the watch root
+    /// as `{}` and the paths in `{paths}` (shell-quoted, space separated) and in
+    /// the file named by `$KADESH_PATHS_FILE`, one per line. The window opens
+    /// with the first event and is not extended by later ones. Pending digests
+    /// are flushed on Ctrl+C.
+    #[serde(default)]
+    pub digest_interval_ms: Option<u64>,
+    /// Send stderr into the stdout pipe (like `2>&1`) so output is captured and
+    /// logged as one interleaved stream. Pooled commands are always merged.
+    #[serde(default)]
+    pub merge_output: bool,
+    /// Pass output straight to kadesh's own stdout and stderr instead of
+    /// capturing it, e.g. for progress bars in the foreground. It is then
+    /// never logged. Such actions always spawn, bypassing the pool.
+    #[serde(default)]
+    pub inherit_output: bool,
+    /// Overrides the global `max-output-bytes` for this action.
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+    /// Overrides the global `umask` for this action.
+    #[serde(default)]
+    pub umask: Option<String>,
+    /// Override the global `run-as-user` / `run-as-group` for this action.
+    #[serde(default)]
+    pub run_as_user: Option<UserId>,
+    #[serde(default)]
+    pub run_as_group: Option<GroupId>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Searched for in the stdout of a successful command. On a match, `then`
+    /// runs on the same path with the first capture group (or the whole match
+    /// if the pattern has none) in `{capture}` (shell-quoted) and
+    /// `$KADESH_CAPTURE`; without a match it is skipped. Pooled commands
+    /// capture stderr along with stdout.
+    #[serde(default)]
+    pub capture_regex: Option<String>,
+    #[serde(default)]
+    pub then: Option<Box<Action>>,
+    /// Run after this action succeeds, or after it finally fails (retries
+    /// spent), on the same path: either the name of an `[action.<name>]` or
+    /// an inline action table. The failure hook gets the error in `{error}`
+    /// (shell-quoted) and `$KADESH_ERROR`, and a command's exit code in
+    /// `{exit_code}` and `$KADESH_EXIT_CODE`. Skipped actions run neither hook;
+    /// a hook's own failure is only logged.
+    #[serde(default)]
+    pub on_success: Option<ActionRef>,
+    #[serde(default)]
+    pub on_failure: Option<ActionRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ActionRef {
+    Named(String),
+    Inline(Box<Action>),
+}
+
+impl ActionRef {
+    pub fn resolve<'a>(&'a self, named_actions: &'a HashMap<String, Action>) -> Option<&'a Action> {
+        match self {
+            ActionRef::Named(name) => named_actions.get(name),
+            ActionRef::Inline(action) => Some(action),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CommandSpec {
+    Single(String),
+    ByKind(BTreeMap<String, String>),
+}
+
+impl Default for CommandSpec {
+    fn default() -> Self {
+        CommandSpec::Single(String::new())
+    }
+}
+
+/// Keys accepted in a command table.
+pub const COMMAND_KEYS: &[&str] = &["create", "modify", "remove", "default"];
+
+impl CommandSpec {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            CommandSpec::Single(command) => command.is_empty(),
+            CommandSpec::ByKind(commands) => commands.is_empty(),
+        }
+    }
+
+    // Events without a kind, such as digests and control requests, take `default`.
+    pub fn for_kind(&self, kind: Option<EventKind>) -> Option<&str> {
+        match self {
+            CommandSpec::Single(command) => Some(command),
+            CommandSpec::ByKind(commands) => kind
+                .and_then(event_kind_to_primary_string)
+                .and_then(|kind| commands.get(kind))
+                .or_else(|| commands.get("default"))
+                .map(String::as_str),
+        }
+    }
+}
+
+impl std::fmt::Display for CommandSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandSpec::Single(command) => f.write_str(command),
+            CommandSpec::ByKind(commands) => {
+                let entries: Vec<String> = commands
+                    .iter()
+                    .map(|(kind, command)| format!("{}: {}", kind, command))
+                    .collect();
+                write!(f, "{{{}}}", entries.join("; "))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    #[default]
+    Command,
+    Copy,
+    Move,
+    Delete,
+    /// Needs a build with the `compress` feature.
+    Compress,
+}
+
+impl ActionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActionKind::Command => "command",
+            ActionKind::Copy => "copy",
+            ActionKind::Move => "move",
+            ActionKind::Delete => "delete",
+            ActionKind::Compress => "compress",
+        }
+    }
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    #[default]
+    Gzip,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Gzip => "gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+impl Action {
+    // None when `command` is a table with nothing for this kind of event.
+    pub fn shell_source(&self, kind: Option<EventKind>) -> Option<&str> {
+        match &self.script {
+            Some(script) => Some(script),
+            None => self.command.for_kind(kind),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match (self.kind, &self.destination) {
+            (ActionKind::Command, _) => match &self.script {
+                Some(script) => format!("script ({} lines)", script.lines().count()),
+                None => self.command.to_string(),
+            },
+            (kind, Some(destination)) => format!("{} to {}", kind.as_str(), destination),
+            (kind, None) => kind.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Filters {
     #[serde(default)]
     pub event_kinds: Option<HashSet<String>>,
+    /// Checked after an event arrives. inotify has no per-file filtering, so
+    /// every directory under the watch is still registered and reports all
+    /// of its files; this saves the action work, not kernel watches or memory.
     #[serde(default)]
     pub extensions: Option<HashSet<String>>,
     #[serde(default)]
     pub ignore_patterns: Vec<String>,
+    #[serde(default)]
+    pub ignore_hidden: bool,
+    #[serde(default)]
+    pub only_hidden: bool,
+    #[serde(default)]
+    pub filter_expr: Option<FilterExpr>,
+    /// Bounds on the number of paths an event carries. Most events have one;
+    /// renames seen from both ends (`rename` with both source and destination)
+    /// carry two.
+    #[serde(default)]
+    pub min_paths: Option<usize>,
+    #[serde(default)]
+    pub max_paths: Option<usize>,
+    /// Only match paths owned by this user / group (name or numeric id). Costs
+    /// an extra stat per path; not checked for remove events or the source of
+    /// a rename, as the path is gone. Unix only.
+    #[serde(default)]
+    pub owner: Option<UserId>,
+    #[serde(default)]
+    pub group: Option<GroupId>,
+    /// Only match files at least / at most this old, e.g. `1h`. Age is how long
+    /// ago `age-from` (`mtime` by default, or `ctime`) was, so a freshly
+    /// created file is as old as the data it was given. Costs an extra stat
+    /// per path; not checked for remove events or the source of a rename, and
+    /// other paths that cannot be stat'ed never match.
+    #[serde(default)]
+    pub min_age: Option<HumanDuration>,
+    #[serde(default)]
+    pub max_age: Option<HumanDuration>,
+    #[serde(default)]
+    pub age_from: AgeSource,
+    /// `true` only matches paths that are symlinks themselves, `false` only
+    /// paths that aren't; the link is not followed. Costs an extra lstat per
+    /// path; not checked for remove events, nor for the source of a rename,
+    /// which is gone by then.
+    #[serde(default)]
+    pub is_symlink: Option<bool>,
 }
 
+static NEXT_WATCH_ID: AtomicU64 = AtomicU64::new(0);
+
 impl WatchConfig {
     pub fn expanded_absolute_path(&self) -> Result<PathBuf> {
+        if let Some(root) = self.resolved_root.get() {
+            return Ok(root.clone());
+        }
+        let root = self.resolve_path()?;
+        Ok(self.resolved_root.get_or_init(|| root).clone())
+    }
+
+    pub fn extension_action(&self, path: &Path) -> Option<&Action> {
+        let ext = path.extension()?.to_str()?;
+        self.by_extension
+            .iter()
+            .find(|(key, _)| key.trim_start_matches('.') == ext)
+            .map(|(_, action)| action)
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.path)
+    }
+
+    pub fn resolved_root(&self) -> Option<&Path> {
+        self.resolved_root.get().map(PathBuf::as_path)
+    }
+
+    // Keys the state kept for the watch between events (seen paths, throttle
+    // windows, digests and so on). Its index can't, as that shifts when a
+    // reload adds, removes or reorders watches; a reload hands the id on to
+    // the same watch in the new config instead.
+    pub fn id(&self) -> u64 {
+        *self
+            .id
+            .get_or_init(|| NEXT_WATCH_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    // Makes this watch continue `previous`. Only takes effect before `id` was
+    // first read.
+    pub fn inherit_id(&self, previous: &WatchConfig) {
+        let _ = self.id.set(previous.id());
+    }
+
+    fn resolve_path(&self) -> Result<PathBuf> {
         let expanded = shellexpand::full(&self.path).map_err(|e| AppError::PathExpansion {
             path: self.path.clone(),
             source: e,
         })?;
         let path = PathBuf::from(expanded.as_ref());
-        path.canonicalize().map_err(|e| {
-            warn!(path = ?path, error = %e, "Failed to canonicalize path, using as-is. Ensure it exists and permissions are correct.");
-            AppError::Io(e)
-        }).or_else(|_| Ok(path))
+        // Canonicalizing a tracked symlink would resolve it to its current target,
+        // so only its directory is resolved.
+        if self.track_symlink
+            && let (Some(parent), Some(name)) = (path.parent(), path.file_name())
+            && let Ok(parent) = parent.canonicalize()
+        {
+            return Ok(parent.join(name));
+        }
+        match path.canonicalize() {
+            Ok(canonical) => Ok(canonical),
+            Err(e) if self.allow_missing && e.kind() == ErrorKind::NotFound => {
+                debug!(path = ?path, "Path does not exist yet, using as-is.");
+                Ok(path)
+            }
+            Err(e) => {
+                warn!(path = ?path, error = %e, "Failed to canonicalize path, using as-is. Ensure it exists and permissions are correct.");
+                Ok(path)
+            }
+        }
     }
 }
 
@@ -67,35 +1018,525 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_exit_on_event() -> String {
+    "any".to_string()
+}
+
+fn default_log_sample_rate() -> f64 {
+    1.0
+}
+
 fn default_debounce_ms() -> u64 {
     500
 }
 
+fn default_transient_window_ms() -> u64 {
+    1000
+}
+
+fn default_stability_interval_ms() -> u64 {
+    500
+}
+
+fn default_broker_buffer() -> usize {
+    1024
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_processor_restart_limit() -> u32 {
+    5
+}
+
+pub const STDIN_CONFIG_PATH: &str = "-";
+
 pub async fn load_config(config_path: &Path) -> Result<Config> {
-    let content =
+    let content = if config_path == Path::new(STDIN_CONFIG_PATH) {
+        read_stdin_config().await?
+    } else {
         tokio::fs::read_to_string(config_path)
             .await
-            .map_err(|e| AppError::ConfigRead {
-                path: config_path.to_path_buf(),
-                source: e,
-            })?;
-    let config: Config = toml::from_str(&content).map_err(|e| AppError::ConfigParse {
+            .map_err(|e| config_read_error(config_path, e))?
+    };
+    let mut config: Config = toml::from_str(&content).map_err(|e| AppError::ConfigParse {
         path: config_path.to_path_buf(),
         source: e,
     })?;
+    config.watches = expand_paths_files(config.watches)?;
 
     if config.watches.is_empty() {
         warn!("Configuration file loaded, but no [[watch]] sections defined");
     }
+    config.validate()?;
     Ok(config)
 }
 
-impl Filters {
-    pub fn matches(&self, event: &notify::Event) -> bool {
-        if let Some(ref kinds) = self.event_kinds {
-            if !kinds.iter().any(|k| event_kind_matches(event.kind, k)) {
-                return false;
+// Replaces every watch with a `paths-file` by one watch per listed path.
+fn expand_paths_files(watches: Vec<WatchConfig>) -> Result<Vec<WatchConfig>> {
+    let mut expanded = Vec::with_capacity(watches.len());
+    for watch in watches {
+        let Some(file) = &watch.paths_file else {
+            expanded.push(watch);
+            continue;
+        };
+        if !watch.path.is_empty() {
+            return Err(AppError::ConfigValidation(format!(
+                "watch '{}' sets both path and paths-file, use one or the other",
+                watch.display_name()
+            )));
+        }
+        let file = PathBuf::from(
+            shellexpand::full(&file.to_string_lossy())
+                .map_err(|e| AppError::PathExpansion {
+                    path: file.display().to_string(),
+                    source: e,
+                })?
+                .as_ref(),
+        );
+        let content =
+            std::fs::read_to_string(&file).map_err(|e| paths_file_read_error(&watch, &file, e))?;
+        let paths: Vec<&str> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        if paths.is_empty() {
+            warn!(paths_file = %file.display(), "paths-file lists no paths, the watch is skipped");
+        }
+        for path in paths {
+            expanded.push(WatchConfig {
+                path: path.to_string(),
+                paths_file: None,
+                ..watch.clone()
+            });
+        }
+    }
+    Ok(expanded)
+}
+
+impl Config {
+    pub fn shell_argv(&self) -> Vec<String> {
+        let configured: Vec<String> = self
+            .shell
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        if !configured.is_empty() {
+            configured
+        } else if cfg!(target_os = "windows") {
+            vec!["cmd".to_string(), "/C".to_string()]
+        } else {
+            vec!["sh".to_string(), "-c".to_string()]
+        }
+    }
+
+    // Checked separately from `validate` since a missing interpreter only warns
+    // outside of `--check`: PATH may differ by the time actions run.
+    pub fn check_shell(&self) -> Result<()> {
+        let argv = self.shell_argv();
+        let interpreter = Path::new(&argv[0]);
+        let found = if interpreter.components().count() > 1 {
+            interpreter.is_file()
+        } else {
+            find_in_path(&argv[0]).is_some()
+        };
+        if found {
+            Ok(())
+        } else {
+            Err(AppError::ShellNotFound(argv[0].clone()))
+        }
+    }
+
+    // Whether an inherited environment variable is passed to actions.
+    pub fn passes_env(&self, name: &str) -> bool {
+        self.env_allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(|glob| glob.matches_name(name)))
+            && !self.env_deny.iter().any(|glob| glob.matches_name(name))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.exec_mode == ExecMode::Pool && self.pool_size == 0 {
+            return Err(AppError::ConfigValidation(
+                "pool-size must be at least 1 when exec-mode is \"pool\"".to_string(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.log_sample_rate) {
+            return Err(AppError::ConfigValidation(format!(
+                "log-sample-rate must be between 0.0 and 1.0, got {}",
+                self.log_sample_rate
+            )));
+        }
+        if self.heartbeat_interval_ms == Some(0) {
+            return Err(AppError::ConfigValidation(
+                "heartbeat-interval-ms must be at least 1".to_string(),
+            ));
+        }
+        let mut debounced_kinds = HashSet::new();
+        for (kind, window_ms) in &self.debounce_by_kind {
+            if let Some(message) = unknown_keyword(kind, DEBOUNCE_KINDS) {
+                return Err(AppError::ConfigValidation(format!(
+                    "debounce-by-kind: {}",
+                    message
+                )));
             }
+            if !debounced_kinds.insert(kind.to_lowercase()) {
+                return Err(AppError::ConfigValidation(format!(
+                    "debounce-by-kind sets '{}' more than once",
+                    kind.to_lowercase()
+                )));
+            }
+            if *window_ms == 0 {
+                return Err(AppError::ConfigValidation(format!(
+                    "debounce-by-kind window for '{}' must be at least 1",
+                    kind
+                )));
+            }
+        }
+        if self.max_actions_per_second == Some(0) {
+            return Err(AppError::ConfigValidation(
+                "max-actions-per-second must be at least 1".to_string(),
+            ));
+        }
+        for (index, watch) in self.watches.iter().enumerate() {
+            if watch.path.is_empty() {
+                return Err(AppError::ConfigValidation(format!(
+                    "watch #{} needs a path or paths-file",
+                    index
+                )));
+            }
+            for action in watch
+                .actions
+                .iter()
+                .chain(watch.by_extension.values())
+                .chain(&watch.on_retarget)
+                .chain(&watch.on_becomes_empty)
+                .chain(&watch.on_becomes_nonempty)
+                .chain(&watch.on_size_exceeded)
+                .chain(&watch.on_size_below)
+            {
+                validate_action(
+                    action,
+                    &self.named_actions,
+                    &format!("watch '{}'", watch.display_name()),
+                )?;
+            }
+            for kind in watch.filters.event_kinds.iter().flatten() {
+                if let Some(message) = unknown_keyword(kind, EVENT_KINDS) {
+                    return Err(AppError::ConfigValidation(format!(
+                        "event-kinds filter on watch '{}': {}",
+                        watch.display_name(),
+                        message
+                    )));
+                }
+            }
+            if let (Some(min), Some(max)) = (watch.filters.min_age, watch.filters.max_age)
+                && min > max
+            {
+                return Err(AppError::ConfigValidation(format!(
+                    "watch '{}' has min-age {} above max-age {}, so nothing can match",
+                    watch.display_name(),
+                    min,
+                    max
+                )));
+            }
+            if let Some(target) = &watch.log_target
+                && !self.log_targets.contains_key(target)
+            {
+                return Err(AppError::ConfigValidation(format!(
+                    "watch '{}' logs to unknown log target '{}'",
+                    watch.display_name(),
+                    target
+                )));
+            }
+            if (watch.on_size_exceeded.is_some() || watch.on_size_below.is_some())
+                && watch.size_limit_bytes.is_none()
+            {
+                return Err(AppError::ConfigValidation(format!(
+                    "watch '{}' sets on-size-exceeded or on-size-below without size-limit-bytes",
+                    watch.display_name()
+                )));
+            }
+            if watch.on_retarget.is_some() && !watch.track_symlink {
+                return Err(AppError::ConfigValidation(format!(
+                    "watch '{}' sets on-retarget without track-symlink",
+                    watch.display_name()
+                )));
+            }
+        }
+        if let Some(exit_on) = &self.exit_on
+            && let Some(message) = unknown_keyword(&exit_on.event, ACTION_EVENTS)
+        {
+            return Err(AppError::ConfigValidation(format!("exit-on: {}", message)));
+        }
+        if let Some(addr) = &self.control_addr {
+            control::bind_address(addr).map_err(AppError::ConfigValidation)?;
+        }
+        for (name, action) in &self.named_actions {
+            validate_action(action, &self.named_actions, &format!("[action.{}]", name))?;
+        }
+        for (name, hook) in [("on-start", &self.on_start), ("on-stop", &self.on_stop)] {
+            let Some(action) = hook else {
+                continue;
+            };
+            if action.kind != ActionKind::Command {
+                return Err(AppError::ConfigValidation(format!(
+                    "{} must be a command action, there is no file to act on",
+                    name
+                )));
+            }
+            if self.watches.is_empty() {
+                return Err(AppError::ConfigValidation(format!(
+                    "{} needs at least one watch to take its settings from",
+                    name
+                )));
+            }
+            validate_action(action, &self.named_actions, name)?;
+        }
+        for route in &self.routes {
+            if let Some(message) = unknown_keyword(&route.when, ACTION_EVENTS) {
+                return Err(AppError::ConfigValidation(format!(
+                    "route running '{}': {}",
+                    route.run, message
+                )));
+            }
+            if !self.named_actions.contains_key(&route.run) {
+                return Err(AppError::ConfigValidation(format!(
+                    "route '{}' runs unknown action '{}'",
+                    route.when, route.run
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_action(
+    action: &Action,
+    named_actions: &HashMap<String, Action>,
+    location: &str,
+) -> Result<()> {
+    if let Some(message) = unknown_keyword(&action.event, ACTION_EVENTS) {
+        return Err(AppError::ConfigValidation(format!(
+            "action on {}: {}",
+            location, message
+        )));
+    }
+    if action.script.is_some() && !action.command.is_empty() {
+        return Err(AppError::ConfigValidation(format!(
+            "action on {} sets both command and script, use one or the other",
+            location
+        )));
+    }
+    if let CommandSpec::ByKind(commands) = &action.command {
+        if commands.is_empty() {
+            return Err(AppError::ConfigValidation(format!(
+                "command table on {} needs at least one of {}",
+                location,
+                COMMAND_KEYS.join(", ")
+            )));
+        }
+        if let Some(key) = commands
+            .keys()
+            .find(|key| !COMMAND_KEYS.contains(&key.as_str()))
+        {
+            return Err(AppError::ConfigValidation(format!(
+                "command table on {} has unknown key '{}', expected one of {}",
+                location,
+                key,
+                COMMAND_KEYS.join(", ")
+            )));
+        }
+    }
+    if matches!(action.kind, ActionKind::Copy | ActionKind::Move) && action.destination.is_none() {
+        return Err(AppError::ConfigValidation(format!(
+            "{} action on {} requires a destination",
+            action.kind.as_str(),
+            location
+        )));
+    }
+    if action.kind == ActionKind::Compress && !cfg!(feature = "compress") {
+        return Err(AppError::ConfigValidation(format!(
+            "compress action on {} needs kadesh built with the `compress` feature",
+            location
+        )));
+    }
+    if action.inherit_output && action.merge_output {
+        return Err(AppError::ConfigValidation(format!(
+            "action on {} sets both inherit-output and merge-output, use one or the other",
+            location
+        )));
+    }
+    if action.digest_interval_ms.is_some() && action.kind != ActionKind::Command {
+        return Err(AppError::ConfigValidation(format!(
+            "digest-interval-ms on {} is only supported for command actions",
+            location
+        )));
+    }
+    if action.capture_regex.is_some() != action.then.is_some() {
+        return Err(AppError::ConfigValidation(format!(
+            "action on {} needs both capture-regex and then, or neither",
+            location
+        )));
+    }
+    if let Some(pattern) = &action.capture_regex {
+        if action.kind != ActionKind::Command || action.inherit_output {
+            return Err(AppError::ConfigValidation(format!(
+                "capture-regex on {} needs a command action whose output is captured",
+                location
+            )));
+        }
+        if let Err(e) = regex::Regex::new(pattern) {
+            return Err(AppError::ConfigValidation(format!(
+                "invalid capture-regex on {}: {}",
+                location, e
+            )));
+        }
+    }
+    if let Some(then) = &action.then {
+        validate_action(then, named_actions, &format!("{} (then)", location))?;
+    }
+    for (hook, name) in [
+        (&action.on_success, "on-success"),
+        (&action.on_failure, "on-failure"),
+    ] {
+        match hook {
+            Some(ActionRef::Named(target)) if !named_actions.contains_key(target) => {
+                return Err(AppError::ConfigValidation(format!(
+                    "{} on {} runs unknown action '{}'",
+                    name, location, target
+                )));
+            }
+            Some(ActionRef::Inline(hook)) => {
+                validate_action(hook, named_actions, &format!("{} ({})", location, name))?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Timestamps in the future count as age zero.
+fn file_age(path: &Path, source: AgeSource) -> Option<Duration> {
+    let metadata = path.symlink_metadata().ok()?;
+    let changed = match source {
+        #[cfg(unix)]
+        AgeSource::Ctime => {
+            use std::os::unix::fs::MetadataExt;
+            let since_epoch = Duration::new(
+                u64::try_from(metadata.ctime()).ok()?,
+                u32::try_from(metadata.ctime_nsec()).ok()?,
+            );
+            SystemTime::UNIX_EPOCH + since_epoch
+        }
+        _ => metadata.modified().ok()?,
+    };
+    Some(
+        SystemTime::now()
+            .duration_since(changed)
+            .unwrap_or_default(),
+    )
+}
+
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        let exe = candidate.with_extension("exe");
+        (cfg!(target_os = "windows") && exe.is_file()).then_some(exe)
+    })
+}
+
+pub fn config_read_error(path: &Path, source: std::io::Error) -> AppError {
+    let hint = match source.kind() {
+        ErrorKind::NotFound => "no such file, pass the config path with --config",
+        ErrorKind::PermissionDenied => "permission denied, check that the file is readable",
+        ErrorKind::IsADirectory => "this is a directory, point --config at a TOML file",
+        ErrorKind::InvalidData => "the file is not valid UTF-8 text",
+        _ => "the file could not be read",
+    };
+    AppError::ConfigRead {
+        path: path.to_path_buf(),
+        hint,
+        source,
+    }
+}
+
+fn paths_file_read_error(watch: &WatchConfig, path: &Path, source: std::io::Error) -> AppError {
+    let hint = match source.kind() {
+        ErrorKind::NotFound => {
+            "no such file, check the watch's paths-file (relative to the working directory)"
+        }
+        ErrorKind::PermissionDenied => "permission denied, check that the paths-file is readable",
+        ErrorKind::IsADirectory => "this is a directory, paths-file must list one path per line",
+        ErrorKind::InvalidData => "the file is not valid UTF-8 text",
+        _ => "the paths-file could not be read",
+    };
+    AppError::PathsFileRead {
+        watch: watch.display_name().to_string(),
+        path: path.to_path_buf(),
+        hint,
+        source,
+    }
+}
+
+async fn read_stdin_config() -> Result<String> {
+    let mut content = String::new();
+    tokio::io::stdin()
+        .read_to_string(&mut content)
+        .await
+        .map_err(|e| config_read_error(Path::new(STDIN_CONFIG_PATH), e))?;
+    if content.trim().is_empty() {
+        return Err(AppError::ConfigStdinEmpty);
+    }
+    Ok(content)
+}
+
+// Renames also name their source, which no longer exists by the time filters
+// stat it. Like removes, such paths are let through the stat-based filters.
+fn renamed_away(kind: EventKind, path: &Path) -> bool {
+    matches!(kind, EventKind::Modify(ModifyKind::Name(_))) && path.symlink_metadata().is_err()
+}
+
+// Renames also name their source, which no longer exists; only the paths that
+// do are judged.
+fn symlink_matches(kind: EventKind, path: &Path, want: bool) -> bool {
+    match path.symlink_metadata() {
+        Ok(metadata) => metadata.file_type().is_symlink() == want,
+        Err(_) => matches!(kind, EventKind::Modify(ModifyKind::Name(_))) || !want,
+    }
+}
+
+impl Filters {
+    fn age_matches(&self, path: &Path) -> bool {
+        let Some(age) = file_age(path, self.age_from) else {
+            return false;
+        };
+        self.min_age.is_none_or(|min| age >= min.0) && self.max_age.is_none_or(|max| age <= max.0)
+    }
+
+    pub fn matches(&self, event: &notify::Event, watch_root: &Path) -> bool {
+        if let Some(ref kinds) = self.event_kinds
+            && !kinds.iter().any(|k| {
+                event_kind_matches(event.kind, event.paths.first().map(PathBuf::as_path), k)
+            })
+        {
+            return false;
+        }
+
+        let path_count = event.paths.len();
+        if self.min_paths.is_some_and(|min| path_count < min)
+            || self.max_paths.is_some_and(|max| path_count > max)
+        {
+            tracing::trace!(path_count, "Event path count out of bounds, skipping.");
+            return false;
         }
 
         for path in &event.paths {
@@ -107,6 +1548,50 @@ impl Filters {
                 tracing::trace!(?path, ?self.ignore_patterns, "Path matched ignore pattern, skipping.");
                 return false;
             }
+            if self.ignore_hidden || self.only_hidden {
+                let hidden = is_hidden_path(path, watch_root);
+                if self.ignore_hidden && hidden {
+                    tracing::trace!(?path, "Path is hidden, skipping.");
+                    return false;
+                }
+                if self.only_hidden && !hidden {
+                    tracing::trace!(?path, "Path is not hidden, skipping.");
+                    return false;
+                }
+            }
+            if (self.owner.is_some() || self.group.is_some())
+                && !event.kind.is_remove()
+                && !renamed_away(event.kind, path)
+                && !ownership::owned_by(path, self.owner, self.group)
+            {
+                tracing::trace!(?path, "Path owner/group mismatch, skipping.");
+                return false;
+            }
+            if (self.min_age.is_some() || self.max_age.is_some())
+                && !event.kind.is_remove()
+                && !renamed_away(event.kind, path)
+                && !self.age_matches(path)
+            {
+                tracing::trace!(?path, "Path age out of bounds, skipping.");
+                return false;
+            }
+            if let Some(want) = self.is_symlink
+                && !event.kind.is_remove()
+                && !symlink_matches(event.kind, path, want)
+            {
+                tracing::trace!(?path, want, "Path symlink status mismatch, skipping.");
+                return false;
+            }
+            if let Some(ref expr) = self.filter_expr
+                && !expr.matches(event.kind, path, watch_root)
+            {
+                tracing::trace!(
+                    ?path,
+                    expr = expr.source(),
+                    "Path rejected by filter expression, skipping."
+                );
+                return false;
+            }
             if let Some(ref exts) = self.extensions {
                 if let Some(ext) = path.extension().and_then(|os| os.to_str()) {
                     let dot_ext = format!(".{}", ext);
@@ -129,24 +1614,155 @@ impl Filters {
     }
 }
 
+fn is_hidden_path(path: &Path, watch_root: &Path) -> bool {
+    // Only components below the watch root count, so watching a dotdir such as
+    // `~/.config` doesn't mark every event inside it as hidden.
+    let relative = path.strip_prefix(watch_root).unwrap_or(path);
+    relative.components().any(|c| match c {
+        Component::Normal(name) => name.to_str().is_some_and(|n| n.starts_with('.')),
+        _ => false,
+    })
+}
+
 fn path_matches_pattern(path: &Path, pattern: &str) -> bool {
-    path.to_str().map_or(false, |s| s.contains(pattern))
+    path.to_str().is_some_and(|s| s.contains(pattern))
+}
+
+/// Keywords accepted by an action's `event` and a route's `when`.
+pub const ACTION_EVENTS: &[&str] = &[
+    "any",
+    "create",
+    "modify",
+    "remove",
+    "rename",
+    "rename_from",
+    "rename_to",
+    "metadata",
+    "xattr",
+    "chmod",
+    "permissions",
+    "write_done",
+    "create_*",
+    "modify_*",
+    "remove_*",
+    "rename_*",
+];
+
+/// Keywords accepted by `event-kinds` filters and `kind:` in filter expressions.
+pub const EVENT_KINDS: &[&str] = &[
+    "access",
+    "create",
+    "modify",
+    "write",
+    "remove",
+    "rename",
+    "metadata",
+    "xattr",
+    "chmod",
+    "permissions",
+    "write_done",
+    "socket",
+    "fifo",
+    "device",
+    "special",
+    "content_change",
+    "rename_to",
+    "rename_from",
+    "create_file",
+    "create_folder",
+    "remove_file",
+    "remove_folder",
+    "create_*",
+    "modify_*",
+    "remove_*",
+    "rename_*",
+];
+
+// The categories `event_kind_to_primary_string` sorts events into.
+const DEBOUNCE_KINDS: &[&str] = &["create", "modify", "remove"];
+
+// An error message for a keyword outside `accepted`, suggesting the closest
+// accepted one if it is only a typo away.
+pub fn unknown_keyword(value: &str, accepted: &[&str]) -> Option<String> {
+    let value = value.to_lowercase();
+    if accepted.contains(&value.as_str()) {
+        return None;
+    }
+    let mut message = format!("unknown event keyword '{}'", value);
+    if let Some((_, closest)) = accepted
+        .iter()
+        .map(|keyword| (edit_distance(&value, keyword), keyword))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+    {
+        message.push_str(&format!(", did you mean '{}'?", closest));
+    }
+    message.push_str(&format!(" Accepted keywords: {}", accepted.join(", ")));
+    Some(message)
 }
 
-fn event_kind_matches(kind: EventKind, kind_str: &str) -> bool {
-    match kind_str.to_lowercase().as_str() {
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+pub fn event_kind_matches(kind: EventKind, path: Option<&Path>, kind_str: &str) -> bool {
+    let kind_str = kind_str.to_lowercase();
+    // `create_*`, `modify_*`, `remove_*` and `rename_*` stand for every variant
+    // of their category, the same as the bare keyword.
+    let kind_str = kind_str.strip_suffix("_*").unwrap_or(&kind_str);
+    match kind_str {
         "access" => kind.is_access(),
         "create" => kind.is_create(),
         "modify" | "write" => kind.is_modify() || kind.is_access(),
         "remove" => kind.is_remove(),
+        "rename" => matches!(kind, EventKind::Modify(ModifyKind::Name(_))),
+        // inotify reports every attribute change (xattrs and chmod included) as
+        // a generic metadata change, so `xattr` only matches on backends that
+        // tell them apart, such as FSEvents; use `metadata` for portable configs.
+        // None of notify's current backends report permission changes on their
+        // own (FSEvents and kqueue fold them in too), so `chmod` only matches
+        // events that carry that detail.
+        "metadata" => matches!(kind, EventKind::Modify(ModifyKind::Metadata(_))),
+        "xattr" => matches!(
+            kind,
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Extended))
+        ),
+        "chmod" | "permissions" => matches!(
+            kind,
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions))
+        ),
+        "write_done" => matches!(
+            kind,
+            EventKind::Access(AccessKind::Close(AccessMode::Write))
+        ),
+        keyword @ ("socket" | "fifo" | "device" | "special") => {
+            special_file_matches(kind, path, keyword)
+        }
         _ => match kind {
             EventKind::Modify(ModifyKind::Data(DataChange::Content))
                 if kind_str == "content_change" =>
             {
                 true
             }
-            EventKind::Modify(ModifyKind::Name(RenameMode::To)) if kind_str == "rename_to" => true,
-            EventKind::Modify(ModifyKind::Name(RenameMode::From)) if kind_str == "rename_from" => {
+            // A stitched rename carries both ends, so it counts for either.
+            EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both))
+                if kind_str == "rename_to" =>
+            {
+                true
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From | RenameMode::Both))
+                if kind_str == "rename_from" =>
+            {
                 true
             }
             EventKind::Create(CreateKind::File) if kind_str == "create_file" => true,
@@ -158,6 +1774,85 @@ fn event_kind_matches(kind: EventKind, kind_str: &str) -> bool {
     }
 }
 
+// Creation or removal of sockets, FIFOs and device nodes. Backends differ:
+// inotify reports these as plain files, so the type comes from stat'ing the
+// path, which only works while it still exists. Removals therefore only match
+// where the backend reports a non-file kind (`Other`), and then only for
+// `special` since the exact type is unknown.
+fn special_file_matches(kind: EventKind, path: Option<&Path>, keyword: &str) -> bool {
+    if !kind.is_create() && !kind.is_remove() {
+        return false;
+    }
+    let file_type = path
+        .and_then(|path| path.symlink_metadata().ok())
+        .map(|m| m.file_type());
+    match file_type {
+        Some(file_type) => {
+            let (socket, fifo, device) = special_file_type(file_type);
+            match keyword {
+                "socket" => socket,
+                "fifo" => fifo,
+                "device" => device,
+                _ => socket || fifo || device,
+            }
+        }
+        None => {
+            keyword == "special"
+                && matches!(
+                    kind,
+                    EventKind::Create(CreateKind::Other) | EventKind::Remove(RemoveKind::Other)
+                )
+        }
+    }
+}
+
+#[cfg(unix)]
+fn special_file_type(file_type: std::fs::FileType) -> (bool, bool, bool) {
+    use std::os::unix::fs::FileTypeExt;
+    (
+        file_type.is_socket(),
+        file_type.is_fifo(),
+        file_type.is_block_device() || file_type.is_char_device(),
+    )
+}
+
+#[cfg(not(unix))]
+fn special_file_type(_file_type: std::fs::FileType) -> (bool, bool, bool) {
+    (false, false, false)
+}
+
+// The kind is checked first since the path may already be gone (removals,
+// rename sources); a removed path of unknown kind is assumed to be a file.
+pub fn is_directory_event(kind: EventKind, path: &Path) -> bool {
+    match kind {
+        EventKind::Create(CreateKind::Folder) | EventKind::Remove(RemoveKind::Folder) => true,
+        EventKind::Create(CreateKind::File) | EventKind::Remove(RemoveKind::File) => false,
+        _ => path.is_dir(),
+    }
+}
+
+/// Device id of the filesystem holding `path`. Paths that are gone, such as
+/// removed files, report the device of their nearest existing ancestor.
+/// Always `None` off unix, so device checks there are skipped.
+pub fn device_id(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(metadata) = path.symlink_metadata() {
+            return Some(metadata.dev());
+        }
+        path.ancestors()
+            .skip(1)
+            .find_map(|dir| dir.metadata().ok())
+            .map(|metadata| metadata.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
 pub fn event_kind_to_primary_string(kind: EventKind) -> Option<&'static str> {
     if kind.is_create() {
         Some("create")
@@ -169,3 +1864,312 @@ pub fn event_kind_to_primary_string(kind: EventKind) -> Option<&'static str> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Config> {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate()?;
+        Ok(config)
+    }
+
+    const WATCH: &str = "\n[[watch]]\npath = \"/srv\"\n";
+
+    #[test]
+    fn unreadable_paths_file_names_the_watch_and_key() {
+        let config: Config = toml::from_str(
+            "[[watch]]\nname = \"logs\"\npaths-file = \"/nonexistent/kadesh-paths\"\n",
+        )
+        .unwrap();
+        let error = expand_paths_files(config.watches).unwrap_err().to_string();
+        assert!(error.contains("watch 'logs'"), "{error}");
+        assert!(error.contains("/nonexistent/kadesh-paths"), "{error}");
+        assert!(error.contains("check the watch's paths-file"), "{error}");
+        assert!(!error.contains("--config"), "{error}");
+    }
+
+    #[test]
+    fn debounce_by_kind_accepts_known_categories() {
+        parse(&format!(
+            "debounce-by-kind = {{ create = 100, modify = 1000 }}{}",
+            WATCH
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn debounce_by_kind_rejects_unknown_duplicate_and_zero_entries() {
+        for table in [
+            "{ crate = 100 }",
+            "{ create = 100, Create = 200 }",
+            "{ remove = 0 }",
+        ] {
+            let toml = format!("debounce-by-kind = {}{}", table, WATCH);
+            assert!(
+                matches!(parse(&toml), Err(AppError::ConfigValidation(_))),
+                "{} was accepted",
+                table
+            );
+        }
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> notify::Event {
+        paths.iter().fold(notify::Event::new(kind), |event, path| {
+            event.add_path(path.to_path_buf())
+        })
+    }
+
+    fn owner_filter(uid: u32) -> Filters {
+        Filters {
+            owner: Some(UserId(uid)),
+            ..Filters::default()
+        }
+    }
+
+    #[test]
+    fn rename_from_and_rename_to_are_action_keywords() {
+        for keyword in ["rename_from", "rename_to"] {
+            assert_eq!(unknown_keyword(keyword, ACTION_EVENTS), None);
+        }
+    }
+
+    #[test]
+    fn rename_keywords_match_their_side_and_stitched_renames() {
+        let name = |mode| EventKind::Modify(ModifyKind::Name(mode));
+        assert!(event_kind_matches(
+            name(RenameMode::From),
+            None,
+            "rename_from"
+        ));
+        assert!(!event_kind_matches(
+            name(RenameMode::To),
+            None,
+            "rename_from"
+        ));
+        assert!(event_kind_matches(name(RenameMode::To), None, "rename_to"));
+        assert!(!event_kind_matches(
+            name(RenameMode::From),
+            None,
+            "rename_to"
+        ));
+        assert!(event_kind_matches(
+            name(RenameMode::Both),
+            None,
+            "rename_from"
+        ));
+        assert!(event_kind_matches(
+            name(RenameMode::Both),
+            None,
+            "rename_to"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owner_filter_lets_the_source_of_a_rename_through() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = tempfile::tempdir().unwrap();
+        let to = dir.path().join("new");
+        std::fs::write(&to, "").unwrap();
+        let uid = to.metadata().unwrap().uid();
+        let from = dir.path().join("old");
+
+        let rename_from = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &[&from],
+        );
+        let rename = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[&from, &to],
+        );
+        assert!(owner_filter(uid).matches(&rename_from, dir.path()));
+        assert!(owner_filter(uid).matches(&rename, dir.path()));
+        assert!(!owner_filter(uid + 1).matches(&rename, dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owner_filter_still_rejects_missing_paths_outside_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let gone = dir.path().join("gone");
+        let create = event(EventKind::Create(CreateKind::File), &[&gone]);
+        assert!(!owner_filter(0).matches(&create, dir.path()));
+    }
+
+    #[test]
+    fn create_wildcard_matches_files_and_folders() {
+        for kind in [CreateKind::File, CreateKind::Folder, CreateKind::Any] {
+            assert!(event_kind_matches(
+                EventKind::Create(kind),
+                None,
+                "create_*"
+            ));
+            assert!(event_kind_matches(
+                EventKind::Create(kind),
+                None,
+                "CREATE_*"
+            ));
+            assert!(!event_kind_matches(
+                EventKind::Create(kind),
+                None,
+                "modify_*"
+            ));
+        }
+        let write = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        assert!(!event_kind_matches(write, None, "create_*"));
+
+        let config = parse(&format!(
+            "{WATCH}[watch.filters]\nevent-kinds = [\"create_*\"]\n"
+        ))
+        .unwrap();
+        let filters = &config.watches[0].filters;
+        let dir = tempfile::tempdir().unwrap();
+        let created = event(EventKind::Create(CreateKind::Folder), &[dir.path()]);
+        assert!(filters.matches(&created, dir.path()));
+        assert!(!filters.matches(&event(write, &[dir.path()]), dir.path()));
+    }
+
+    #[test]
+    fn only_known_categories_take_a_wildcard() {
+        let filter = |kind: &str| format!("{WATCH}[watch.filters]\nevent-kinds = [\"{kind}\"]\n");
+        assert!(parse(&filter("remove_*")).is_ok());
+        assert!(parse(&filter("access_*")).is_err());
+        assert!(parse(&filter("create*")).is_err());
+    }
+
+    #[test]
+    fn rename_wildcard_matches_both_directions() {
+        let name = |mode| EventKind::Modify(ModifyKind::Name(mode));
+        for mode in [RenameMode::From, RenameMode::To, RenameMode::Both] {
+            assert!(event_kind_matches(name(mode), None, "rename_*"));
+            assert!(event_kind_matches(name(mode), None, "modify_*"));
+            assert!(!event_kind_matches(name(mode), None, "remove_*"));
+        }
+    }
+
+    #[test]
+    fn age_filter_lets_the_source_of_a_rename_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let to = dir.path().join("new");
+        std::fs::write(&to, "").unwrap();
+        let from = dir.path().join("old");
+        let filters = Filters {
+            min_age: Some(HumanDuration(Duration::from_secs(3600))),
+            ..Filters::default()
+        };
+
+        let rename_from = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &[&from],
+        );
+        assert!(filters.matches(&rename_from, dir.path()));
+        // The destination was just written, so it is too young either way.
+        let rename_to = event(EventKind::Modify(ModifyKind::Name(RenameMode::To)), &[&to]);
+        let rename = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[&from, &to],
+        );
+        assert!(!filters.matches(&rename_to, dir.path()));
+        assert!(!filters.matches(&rename, dir.path()));
+    }
+
+    #[test]
+    fn xattr_and_metadata_keywords_match_metadata_events() {
+        let metadata = |kind| EventKind::Modify(ModifyKind::Metadata(kind));
+        assert!(event_kind_matches(
+            metadata(MetadataKind::Extended),
+            None,
+            "xattr"
+        ));
+        assert!(event_kind_matches(
+            metadata(MetadataKind::Extended),
+            None,
+            "metadata"
+        ));
+        assert!(event_kind_matches(
+            metadata(MetadataKind::Any),
+            None,
+            "metadata"
+        ));
+        // inotify's generic attribute change carries no xattr detail.
+        assert!(!event_kind_matches(
+            metadata(MetadataKind::Any),
+            None,
+            "xattr"
+        ));
+        assert!(!event_kind_matches(
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            None,
+            "metadata"
+        ));
+    }
+
+    #[test]
+    fn chmod_keywords_only_match_permission_changes() {
+        let metadata = |kind| EventKind::Modify(ModifyKind::Metadata(kind));
+        for keyword in ["chmod", "permissions"] {
+            assert!(event_kind_matches(
+                metadata(MetadataKind::Permissions),
+                None,
+                keyword
+            ));
+            assert!(!event_kind_matches(
+                metadata(MetadataKind::Ownership),
+                None,
+                keyword
+            ));
+            assert!(!event_kind_matches(
+                metadata(MetadataKind::Any),
+                None,
+                keyword
+            ));
+        }
+        assert_eq!(
+            event_kind_to_primary_string(metadata(MetadataKind::Permissions)),
+            Some("modify")
+        );
+    }
+
+    #[test]
+    fn lifecycle_hooks_need_a_watch() {
+        for hook in ["on-start", "on-stop"] {
+            let hook = format!("[{hook}]\nevent = \"any\"\ncommand = \"true\"\n");
+            assert!(parse(&hook).is_err());
+            assert!(parse(&format!("{WATCH}{hook}")).is_ok());
+        }
+    }
+
+    fn symlink_filter(want: bool) -> Filters {
+        let toml = format!("{WATCH}[watch.filters]\nis-symlink = {want}\n");
+        parse(&toml).unwrap().watches.remove(0).filters
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_symlink_tells_links_from_regular_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        let link = dir.path().join("link");
+        let dangling = dir.path().join("dangling");
+        std::fs::write(&file, "").unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("missing"), &dangling).unwrap();
+        let created = |path: &Path| event(EventKind::Create(CreateKind::Any), &[path]);
+
+        let (links, others) = (symlink_filter(true), symlink_filter(false));
+        assert!(links.matches(&created(&link), dir.path()));
+        assert!(links.matches(&created(&dangling), dir.path()));
+        assert!(!links.matches(&created(&file), dir.path()));
+        assert!(others.matches(&created(&file), dir.path()));
+        assert!(!others.matches(&created(&link), dir.path()));
+        assert!(!others.matches(&created(&dangling), dir.path()));
+
+        let removed = event(
+            EventKind::Remove(RemoveKind::Any),
+            &[&dir.path().join("gone")],
+        );
+        assert!(links.matches(&removed, dir.path()));
+    }
+}
@@ -0,0 +1,377 @@
+//! A minimal HTTP endpoint for running named actions on demand, enabled with
+//! `control-addr`.
+//!
+//! `POST /actions/<name>` runs `[action.<name>]` as if a route had matched,
+//! with optional `path` (defaults to the watch root, and must lie under it)
+//! and `watch` (index or name, defaults to the first watch) query parameters. The action is started
+//! in the background and the request answered with 202; its outcome only shows
+//! up in the log. `POST /forget?path=<path>` makes a `once-per-path` watch
+//! act on that path again. `GET /history` returns the recent events kept by
+//! `history-size` as JSON, and `GET /watches` the paths currently registered
+//! with the watcher. With `control-token` set, requests must carry
+//! `Authorization: Bearer <token>`.
+
+use crate::actions::ActionContext;
+use crate::config::Config;
+use crate::errors::AppError;
+use crate::history;
+use crate::once;
+use crate::watcher::FsWatcher;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info, warn};
+
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A bare port binds to localhost only; anything reachable from elsewhere has to
+// be asked for explicitly.
+pub fn bind_address(control_addr: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = control_addr.parse::<u16>() {
+        return Ok(SocketAddr::from(([127, 0, 0, 1], port)));
+    }
+    control_addr
+        .parse()
+        .map_err(|e| format!("invalid control-addr '{}': {}", control_addr, e))
+}
+
+pub async fn serve(
+    control_addr: String,
+    config_rx: watch::Receiver<Arc<Config>>,
+    shutdown_tx: mpsc::Sender<AppError>,
+    watcher: Arc<Mutex<FsWatcher>>,
+) {
+    let addr = match bind_address(&control_addr) {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!(error = %e, "Control endpoint disabled");
+            return;
+        }
+    };
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(%addr, error = %e, "Failed to bind control endpoint");
+            return;
+        }
+    };
+    if !addr.ip().is_loopback() && config_rx.borrow().control_token.is_none() {
+        warn!(%addr, "Control endpoint is reachable from other hosts without a control-token");
+    }
+    info!(%addr, "Control endpoint listening");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept control connection");
+                continue;
+            }
+        };
+        let config = Arc::clone(&config_rx.borrow());
+        let shutdown_tx = shutdown_tx.clone();
+        let watcher = Arc::clone(&watcher);
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, config, shutdown_tx, watcher).await {
+                debug!(%peer, error = %e, "Control connection failed");
+            }
+        });
+    }
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    config: Arc<Config>,
+    shutdown_tx: mpsc::Sender<AppError>,
+    watcher: Arc<Mutex<FsWatcher>>,
+) -> std::io::Result<()> {
+    let request = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream)).await??;
+    let (status, body) = match &request {
+        Some(request) => respond(request, &config, &shutdown_tx, &watcher),
+        None => (400, "malformed request".to_string()),
+    };
+    let content_type = match &request {
+        Some(request)
+            if status == 200
+                && (request.target.starts_with("/history")
+                    || request.target.starts_with("/watches")) =>
+        {
+            "application/json"
+        }
+        _ => "text/plain",
+    };
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        reason,
+        content_type,
+        body.len() + 1,
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+struct Request {
+    method: String,
+    target: String,
+    authorization: Option<String>,
+}
+
+// Only the request line and headers are read; a body, if any, is ignored.
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<Request>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    let Ok(head) = String::from_utf8(head) else {
+        return Ok(None);
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let authorization = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .map(|(_, value)| value.trim().to_string());
+    Ok(Some(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        authorization,
+    }))
+}
+
+fn respond(
+    request: &Request,
+    config: &Arc<Config>,
+    shutdown_tx: &mpsc::Sender<AppError>,
+    watcher: &Mutex<FsWatcher>,
+) -> (u16, String) {
+    if let Some(token) = &config.control_token {
+        let presented = request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+            return (401, "missing or invalid token".to_string());
+        }
+    }
+    let (route, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((&request.target, ""));
+    let mut path = None;
+    let mut watch = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let Some(value) = percent_decode(value) else {
+            return (400, format!("invalid value for '{}'", key));
+        };
+        match key {
+            "path" => path = Some(PathBuf::from(value)),
+            "watch" => watch = Some(value),
+            _ => return (400, format!("unknown parameter '{}'", key)),
+        }
+    }
+    let watch_index = match &watch {
+        None => 0,
+        Some(watch) => match config
+            .watches
+            .iter()
+            .position(|candidate| candidate.display_name() == watch)
+            .or_else(|| {
+                watch
+                    .parse()
+                    .ok()
+                    .filter(|index| *index < config.watches.len())
+            }) {
+            Some(index) => index,
+            None => return (404, format!("unknown watch '{}'", watch)),
+        },
+    };
+
+    if route == "/history" {
+        if request.method != "GET" {
+            return (405, "use GET".to_string());
+        }
+        return match history::to_json() {
+            Ok(json) => (200, json),
+            Err(e) => (500, format!("failed to serialize history: {}", e)),
+        };
+    }
+    if route == "/watches" {
+        if request.method != "GET" {
+            return (405, "use GET".to_string());
+        }
+        let watched = watcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .watched_paths();
+        return match serde_json::to_string(&watched) {
+            Ok(json) => (200, json),
+            Err(e) => (500, format!("failed to serialize watches: {}", e)),
+        };
+    }
+    if route == "/forget" {
+        if request.method != "POST" {
+            return (405, "use POST".to_string());
+        }
+        let Some(path) = path else {
+            return (400, "pass the path to forget".to_string());
+        };
+        let Some(watch_config) = config.watches.get(watch_index) else {
+            return (404, "no watches are configured".to_string());
+        };
+        if let Err(rejected) = check_within_watch(&path, watch_config.resolved_root()) {
+            return rejected;
+        }
+        return if once::forget(watch_config, &path) {
+            info!(path = %path.display(), watch = watch_config.display_name(), "Forgot seen path on request");
+            (200, format!("forgot {}", path.display()))
+        } else {
+            (404, format!("{} was not seen", path.display()))
+        };
+    }
+    let Some(name) = route.strip_prefix("/actions/") else {
+        return (
+            404,
+            "expected /actions/<name>, /forget, /history or /watches".to_string(),
+        );
+    };
+    if request.method != "POST" {
+        return (405, "use POST".to_string());
+    }
+    let Some(name) = percent_decode(name) else {
+        return (400, "invalid action name".to_string());
+    };
+    let Some(action) = config.named_actions.get(&name) else {
+        return (404, format!("unknown action '{}'", name));
+    };
+
+    let Some(watch_config) = config.watches.get(watch_index) else {
+        return (404, "no watches are configured".to_string());
+    };
+    let Some(path) = path.or_else(|| watch_config.resolved_root().map(PathBuf::from)) else {
+        return (400, "watch root is unresolved, pass a path".to_string());
+    };
+    if let Err(rejected) = check_within_watch(&path, watch_config.resolved_root()) {
+        return rejected;
+    }
+
+    info!(action = %name, path = %path.display(), watch = watch_config.display_name(), "Running action on request");
+    let context = ActionContext::default().for_watch(watch_index, watch_config);
+    crate::spawn_action(config, watch_index, action, &path, context, shutdown_tx);
+    (202, format!("started '{}' on {}", name, path.display()))
+}
+
+// Requested paths have to lie under the watch, so the endpoint cannot be used
+// to run actions on arbitrary files. `..` is refused rather than resolved, as
+// the path need not exist.
+fn check_within_watch(path: &Path, root: Option<&Path>) -> Result<(), (u16, String)> {
+    let Some(root) = root else {
+        return Err((400, "watch root is unresolved".to_string()));
+    };
+    if !path.starts_with(root) || path.components().any(|part| part == Component::ParentDir) {
+        return Err((
+            400,
+            format!("{} is not under {}", path.display(), root.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes();
+    while let Some(byte) = chars.next() {
+        match byte {
+            b'%' => {
+                let hex = [chars.next()?, chars.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(config: &Config, target: &str) -> (u16, String) {
+        let request = Request {
+            method: "POST".to_string(),
+            target: target.to_string(),
+            authorization: None,
+        };
+        let watcher = Mutex::new(FsWatcher::new(config, |_| {}).unwrap());
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        respond(&request, &Arc::new(config.clone()), &shutdown_tx, &watcher)
+    }
+
+    fn config(toml: &str) -> Config {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        for watch in &config.watches {
+            watch.expanded_absolute_path().unwrap();
+        }
+        config
+    }
+
+    const NAMED: &str = r#"
+[action.reindex]
+event = "any"
+command = "true"
+"#;
+
+    #[test]
+    fn without_watches_requests_get_not_found() {
+        let config = config(NAMED);
+        assert_eq!(post(&config, "/actions/reindex").0, 404);
+        assert_eq!(post(&config, "/forget?path=/srv/in/a").0, 404);
+    }
+
+    #[test]
+    fn paths_outside_the_watch_are_rejected() {
+        let config = config(&format!("[[watch]]\npath = \"/srv/in\"\n{NAMED}"));
+        for path in ["/etc/passwd", "/srv/input/a", "/srv/in/../secret"] {
+            let (status, _) = post(&config, &format!("/actions/reindex?path={path}"));
+            assert_eq!(status, 400, "{path}");
+            let (status, _) = post(&config, &format!("/forget?path={path}"));
+            assert_eq!(status, 400, "{path}");
+        }
+        assert_eq!(post(&config, "/forget?path=/srv/in/a").0, 404);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::actions::{self, ActionContext};
+use crate::config::{Action, Config, WatchConfig};
+use crate::errors::{AppError, Result};
+use notify::EventKind;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+use tracing::{error, info, warn};
+
+// Appends from concurrent actions are serialized so records never interleave.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+// One JSON object per line. The full action is kept so a replay runs it exactly
+// as configured at the time of failure; `command` is for human readers. The
+// watch's name and root identify it, since its index shifts when watches are
+// added, removed or reordered.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeadLetter {
+    pub timestamp: String,
+    pub watch_index: usize,
+    pub watch: String,
+    // Missing from records written before it was added.
+    #[serde(default)]
+    pub watch_root: Option<PathBuf>,
+    pub event: Option<EventKind>,
+    pub path: PathBuf,
+    pub command: String,
+    pub error: String,
+    pub attempts: u32,
+    pub action: Action,
+}
+
+impl DeadLetter {
+    pub fn new(action: &Action, path: &Path, context: &ActionContext, error: &AppError) -> Self {
+        let attempts = match error {
+            AppError::ActionBudgetExhausted { attempts, .. } => *attempts,
+            _ => action.retries + 1,
+        };
+        DeadLetter {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            watch_index: context.watch_index,
+            watch: context.watch_name.clone(),
+            watch_root: context.watch_root.clone(),
+            event: context.event_kind,
+            path: path.to_path_buf(),
+            command: action.describe(),
+            error: error.to_string(),
+            attempts,
+            action: action.clone(),
+        }
+    }
+}
+
+pub fn append(file: &Path, record: &DeadLetter) -> Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut out = OpenOptions::new().create(true).append(true).open(file)?;
+    out.write_all(line.as_bytes())?;
+    out.flush()?;
+    out.sync_data()?;
+    Ok(())
+}
+
+pub async fn replay(config: &Config, file: &Path) -> Result<()> {
+    let reader = BufReader::new(std::fs::File::open(file)?);
+    let (mut replayed, mut failed) = (0, 0);
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: DeadLetter =
+            serde_json::from_str(&line).map_err(|source| AppError::DeadLetterParse {
+                path: file.to_path_buf(),
+                line: number + 1,
+                source,
+            })?;
+        let watch = match recorded_watch(config, &record) {
+            Ok(watch) => watch,
+            Err(reason) => {
+                warn!(
+                    line = number + 1,
+                    watch = %record.watch,
+                    path = %record.path.display(),
+                    reason,
+                    "Recorded watch no longer matches the config, skipping"
+                );
+                failed += 1;
+                continue;
+            }
+        };
+        let context = ActionContext {
+            event_kind: record.event,
+            ..ActionContext::default()
+        }
+        .for_watch(record.watch_index, watch);
+
+        info!(line = number + 1, command = %record.command, path = %record.path.display(), "Replaying action");
+        match actions::executor()
+            .execute(config, watch, &record.action, &record.path, &context)
+            .await
+        {
+            Ok(()) => replayed += 1,
+            Err(e) => {
+                error!(line = number + 1, error = %e, "Replay failed");
+                failed += 1;
+            }
+        }
+    }
+    info!(replayed, failed, "Replay finished");
+    if failed > 0 {
+        return Err(AppError::ReplayFailed {
+            failed,
+            total: replayed + failed,
+        });
+    }
+    Ok(())
+}
+
+// The watch at the recorded index, as long as it is still the same watch and
+// the path still falls under it. Replaying with another watch's settings, or
+// on a path it no longer covers, would not repeat what failed.
+fn recorded_watch<'a>(
+    config: &'a Config,
+    record: &DeadLetter,
+) -> std::result::Result<&'a WatchConfig, &'static str> {
+    let watch = config
+        .watches
+        .get(record.watch_index)
+        .ok_or("the watch no longer exists")?;
+    if watch.display_name() != record.watch {
+        return Err("another watch now has its place");
+    }
+    // Also populates the resolved root used for `{relative}` and KADESH_WATCH_ROOT.
+    let root = watch
+        .expanded_absolute_path()
+        .map_err(|_| "the watch path no longer resolves")?;
+    if record
+        .watch_root
+        .as_ref()
+        .is_some_and(|recorded| *recorded != root)
+    {
+        return Err("the watch now points elsewhere");
+    }
+    if !record.path.starts_with(&root) {
+        return Err("the path is outside the watch");
+    }
+    Ok(watch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    fn record(config: &Config, watch_index: usize, path: &str) -> DeadLetter {
+        let watch = &config.watches[watch_index];
+        watch.expanded_absolute_path().unwrap();
+        let context = ActionContext::default().for_watch(watch_index, watch);
+        let error = AppError::ConfigValidation("failed".into());
+        DeadLetter::new(&watch.actions[0], Path::new(path), &context, &error)
+    }
+
+    const TWO_WATCHES: &str = r#"
+[[watch]]
+name = "in"
+path = "/srv/in"
+[[watch.actions]]
+event = "create"
+command = "true"
+
+[[watch]]
+name = "out"
+path = "/srv/out"
+[[watch.actions]]
+event = "create"
+command = "true"
+"#;
+
+    #[test]
+    fn replays_on_the_recorded_watch() {
+        let config = config(TWO_WATCHES);
+        let record = record(&config, 1, "/srv/out/report.txt");
+        let watch = recorded_watch(&config, &record).unwrap();
+        assert_eq!(watch.display_name(), "out");
+    }
+
+    #[test]
+    fn skips_when_watches_were_reordered() {
+        let written = config(TWO_WATCHES);
+        let record = record(&written, 1, "/srv/out/report.txt");
+        // "out" is now first and "in" holds index 1.
+        let (first, second) = TWO_WATCHES.split_at(TWO_WATCHES.rfind("[[watch]]").unwrap());
+        let reordered = config(&format!("{second}\n{first}"));
+        assert_eq!(
+            recorded_watch(&reordered, &record).unwrap_err(),
+            "another watch now has its place"
+        );
+    }
+
+    #[test]
+    fn skips_when_the_watch_root_moved() {
+        let written = config(TWO_WATCHES);
+        let record = record(&written, 0, "/srv/in/report.txt");
+        let moved = config(&TWO_WATCHES.replace("/srv/in", "/srv/elsewhere"));
+        assert_eq!(
+            recorded_watch(&moved, &record).unwrap_err(),
+            "the watch now points elsewhere"
+        );
+    }
+
+    #[test]
+    fn skips_paths_outside_the_watch() {
+        let config = config(TWO_WATCHES);
+        let record = record(&config, 0, "/srv/out/report.txt");
+        assert_eq!(
+            recorded_watch(&config, &record).unwrap_err(),
+            "the path is outside the watch"
+        );
+    }
+
+    #[test]
+    fn old_records_without_a_root_still_parse() {
+        let config = config(TWO_WATCHES);
+        let mut json = serde_json::to_value(record(&config, 0, "/srv/in/a")).unwrap();
+        json.as_object_mut().unwrap().remove("watch_root");
+        let record: DeadLetter = serde_json::from_value(json).unwrap();
+        assert!(recorded_watch(&config, &record).is_ok());
+    }
+}
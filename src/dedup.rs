@@ -0,0 +1,118 @@
+use notify::{Event, EventKind};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+const MAX_TRACKED_EVENTS: usize = 1024;
+
+type EventKey = (EventKind, Vec<PathBuf>);
+
+pub struct RecentEvents {
+    window: Duration,
+    seen: HashMap<EventKey, Instant>,
+    order: VecDeque<(EventKey, Instant)>,
+}
+
+impl RecentEvents {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn is_duplicate(&mut self, event: &Event, now: Instant) -> bool {
+        if self.window.is_zero() {
+            return false;
+        }
+        self.prune(now);
+
+        let key = (event.kind, event.paths.clone());
+        if self.seen.contains_key(&key) {
+            return true;
+        }
+        self.seen.insert(key.clone(), now);
+        self.order.push_back((key, now));
+        false
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((key, seen_at)) = self.order.front() {
+            let expired = now.duration_since(*seen_at) >= self.window;
+            if !expired && self.order.len() < MAX_TRACKED_EVENTS {
+                break;
+            }
+            if self.seen.get(key) == Some(seen_at) {
+                self.seen.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, ModifyKind};
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(PathBuf::from(path))
+    }
+
+    #[test]
+    fn repeats_within_the_window_are_suppressed() {
+        let mut recent = RecentEvents::new(Duration::from_millis(100));
+        let modify = event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/a");
+        let start = Instant::now();
+        assert!(!recent.is_duplicate(&modify, start));
+        assert!(recent.is_duplicate(&modify, start + Duration::from_millis(10)));
+        assert!(recent.is_duplicate(&modify, start + Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn repeats_after_the_window_pass_again() {
+        let mut recent = RecentEvents::new(Duration::from_millis(100));
+        let modify = event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/a");
+        let start = Instant::now();
+        assert!(!recent.is_duplicate(&modify, start));
+        assert!(!recent.is_duplicate(&modify, start + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn other_kinds_and_paths_are_not_duplicates() {
+        let mut recent = RecentEvents::new(Duration::from_millis(100));
+        let now = Instant::now();
+        assert!(!recent.is_duplicate(&event(EventKind::Create(CreateKind::File), "/a"), now));
+        assert!(!recent.is_duplicate(&event(EventKind::Create(CreateKind::File), "/b"), now));
+        assert!(!recent.is_duplicate(
+            &event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/a"),
+            now
+        ));
+    }
+
+    #[test]
+    fn zero_window_disables_suppression() {
+        let mut recent = RecentEvents::new(Duration::ZERO);
+        let create = event(EventKind::Create(CreateKind::File), "/a");
+        let now = Instant::now();
+        assert!(!recent.is_duplicate(&create, now));
+        assert!(!recent.is_duplicate(&create, now));
+    }
+
+    #[test]
+    fn tracking_is_bounded() {
+        let mut recent = RecentEvents::new(Duration::from_secs(60));
+        let now = Instant::now();
+        for i in 0..MAX_TRACKED_EVENTS * 2 {
+            recent.is_duplicate(
+                &event(EventKind::Create(CreateKind::File), &format!("/{}", i)),
+                now,
+            );
+        }
+        assert!(recent.seen.len() <= MAX_TRACKED_EVENTS);
+        assert!(recent.order.len() <= MAX_TRACKED_EVENTS);
+    }
+}
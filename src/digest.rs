@@ -0,0 +1,79 @@
+use crate::actions::ActionContext;
+use crate::config::Action;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+};
+
+static BUFFERS: LazyLock<Mutex<HashMap<DigestKey, Digest>>> = LazyLock::new(Mutex::default);
+
+// Identifies an action across events and reloads: the watch it fired for and
+// what it runs.
+type DigestKey = (u64, String);
+
+pub struct Digest {
+    // Position of the watch in the config the digest was opened under.
+    pub watch_index: usize,
+    pub watch_id: u64,
+    pub action: Action,
+    // In first-seen order, without duplicates.
+    pub paths: Vec<PathBuf>,
+    // Context of the first event in the window; its rename and line fields are
+    // cleared since they describe a single event.
+    pub context: ActionContext,
+}
+
+// Adds `path` to the action's pending digest. Returns true when this opened a
+// new window, in which case the caller is responsible for flushing it once the
+// interval has passed.
+pub fn add(
+    watch_index: usize,
+    watch_id: u64,
+    action: &Action,
+    path: &Path,
+    context: &ActionContext,
+) -> bool {
+    let mut buffers = BUFFERS.lock().unwrap_or_else(PoisonError::into_inner);
+    let key = (watch_id, action.describe());
+    if let Some(digest) = buffers.get_mut(&key) {
+        if !digest.paths.iter().any(|seen| seen == path) {
+            digest.paths.push(path.to_path_buf());
+        }
+        return false;
+    }
+    let context = ActionContext {
+        rename_from: None,
+        rename_to: None,
+        line: None,
+        ..context.clone()
+    };
+    buffers.insert(
+        key,
+        Digest {
+            watch_index,
+            watch_id,
+            action: action.clone(),
+            paths: vec![path.to_path_buf()],
+            context,
+        },
+    );
+    true
+}
+
+pub fn take(watch_id: u64, action: &Action) -> Option<Digest> {
+    BUFFERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&(watch_id, action.describe()))
+}
+
+// Everything still buffered, for a final flush on shutdown.
+pub fn take_all() -> Vec<Digest> {
+    BUFFERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .drain()
+        .map(|(_, digest)| digest)
+        .collect()
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+};
+
+#[derive(Default)]
+struct SizeState {
+    exceeded: bool,
+    // A walk is under way; events arriving meanwhile only mark it `dirty` so it
+    // walks again once done, rather than starting walks of their own.
+    walking: bool,
+    dirty: bool,
+}
+
+static STATE: LazyLock<Mutex<HashMap<u64, SizeState>>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    Exceeded(u64),
+    Below(u64),
+}
+
+// Recomputes the watch's total size and reports whether it crossed `limit`
+// since the last check. Every check walks the whole tree, so its cost grows
+// with the number of files; bursts of events share a walk. Returns None while
+// another event's walk is running, as that one reports for both.
+pub async fn check(watch_id: u64, root: &Path, recursive: bool, limit: u64) -> Option<Crossing> {
+    {
+        let mut state = STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = state.entry(watch_id).or_default();
+        if state.walking {
+            state.dirty = true;
+            return None;
+        }
+        state.walking = true;
+    }
+    loop {
+        let root = root.to_path_buf();
+        let size = tokio::task::spawn_blocking(move || total_size(root, recursive))
+            .await
+            .unwrap_or_default();
+        let mut state = STATE.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = state.entry(watch_id).or_default();
+        if std::mem::take(&mut state.dirty) {
+            continue;
+        }
+        state.walking = false;
+        let exceeded = size > limit;
+        if exceeded == std::mem::replace(&mut state.exceeded, exceeded) {
+            return None;
+        }
+        return Some(if exceeded {
+            Crossing::Exceeded(size)
+        } else {
+            Crossing::Below(size)
+        });
+    }
+}
+
+// Apparent size of the files below `root`. Symlinks are not followed, and
+// entries that vanish or can't be read mid-walk are skipped.
+fn total_size(root: PathBuf, recursive: bool) -> u64 {
+    let mut total = 0;
+    let mut dirs = vec![root];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                if recursive {
+                    dirs.push(entry.path());
+                }
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+    total
+}
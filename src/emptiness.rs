@@ -0,0 +1,136 @@
+use notify::EventKind;
+use notify::event::{ModifyKind, RenameMode};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+};
+use tracing::debug;
+
+// Directories tracked before those no longer on disk are dropped.
+const MAX_TRACKED_DIRS: usize = 1024;
+
+// Last known emptiness of each directory seen under a watch with an
+// `on-becomes-empty` or `on-becomes-nonempty` hook, keyed by watch.
+static EMPTY: LazyLock<Mutex<HashMap<(u64, PathBuf), bool>>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    BecameEmpty,
+    BecameNonEmpty,
+}
+
+// Whether an event can change how many entries its paths' directories hold.
+pub fn is_relevant(kind: EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
+// Re-reads the directories holding `paths` (those under `root`) and returns
+// the ones whose emptiness changed since they were last seen. A directory not
+// seen before is assumed to have been non-empty if something was removed from
+// it, and empty if everything now in it came with this event.
+pub fn transitions(
+    watch_id: u64,
+    root: &Path,
+    kind: EventKind,
+    paths: &[PathBuf],
+) -> Vec<(PathBuf, Transition)> {
+    let removed = matches!(
+        kind,
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From))
+    );
+    let mut dirs: Vec<&Path> = paths
+        .iter()
+        .filter_map(|path| path.parent())
+        .filter(|dir| dir.starts_with(root))
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+
+    let mut known = EMPTY.lock().unwrap_or_else(PoisonError::into_inner);
+    // A removed or renamed directory has no state left to track, and neither
+    // do the directories below it.
+    let gone = match kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => &paths[..paths.len().min(1)],
+        _ if removed => paths,
+        _ => &[],
+    };
+    for path in gone {
+        known.retain(|(id, dir), _| *id != watch_id || !dir.starts_with(path));
+    }
+    if known.len() >= MAX_TRACKED_DIRS {
+        known.retain(|(_, dir), _| dir.is_dir());
+        if known.len() >= MAX_TRACKED_DIRS {
+            // Every tracked directory still exists; start over rather than
+            // re-reading them all on each event.
+            debug!(dirs = known.len(), "Forgetting directory emptiness");
+            known.clear();
+        }
+    }
+    let mut changed = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            known.remove(&(watch_id, dir.to_path_buf()));
+            continue;
+        };
+        let children: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        let is_empty = children.is_empty();
+        let was_empty = match known.insert((watch_id, dir.to_path_buf()), is_empty) {
+            Some(was_empty) => was_empty,
+            None if removed => false,
+            None => children.iter().all(|child| paths.contains(child)),
+        };
+        match (was_empty, is_empty) {
+            (false, true) => changed.push((dir.to_path_buf(), Transition::BecameEmpty)),
+            (true, false) => changed.push((dir.to_path_buf(), Transition::BecameNonEmpty)),
+            _ => {}
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, RemoveKind};
+
+    fn tracked(watch_id: u64, dir: &Path) -> bool {
+        EMPTY
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(&(watch_id, dir.to_path_buf()))
+    }
+
+    #[test]
+    fn removed_and_renamed_directories_are_forgotten() {
+        let root = tempfile::tempdir().unwrap();
+        let outer = root.path().join("outer");
+        let inner = outer.join("inner");
+        std::fs::create_dir_all(&inner).unwrap();
+        let file = inner.join("a.txt");
+        std::fs::write(&file, "a").unwrap();
+        let create = EventKind::Create(CreateKind::File);
+        transitions(u64::MAX, root.path(), create, std::slice::from_ref(&file));
+        transitions(u64::MAX, root.path(), create, std::slice::from_ref(&inner));
+        assert!(tracked(u64::MAX, &inner));
+        assert!(tracked(u64::MAX, &outer));
+
+        std::fs::remove_dir_all(&outer).unwrap();
+        let remove = EventKind::Remove(RemoveKind::Folder);
+        transitions(u64::MAX, root.path(), remove, std::slice::from_ref(&outer));
+        assert!(!tracked(u64::MAX, &inner));
+        assert!(!tracked(u64::MAX, &outer));
+
+        let moved = root.path().join("moved");
+        std::fs::create_dir_all(&inner).unwrap();
+        std::fs::write(&file, "a").unwrap();
+        transitions(u64::MAX, root.path(), create, std::slice::from_ref(&file));
+        std::fs::rename(&outer, &moved).unwrap();
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        transitions(u64::MAX, root.path(), rename, &[outer.clone(), moved]);
+        assert!(!tracked(u64::MAX, &inner));
+    }
+}
@@ -6,9 +6,10 @@ pub enum AppError {
     #[error("IO Error: {0}")]
     Io(#[from] std::io::Error),
 
-    #[error("Configuration error: Failed to read config file {path}: {source}")]
+    #[error("Configuration error: Failed to read config file {path}: {source} ({hint})")]
     ConfigRead {
         path: PathBuf,
+        hint: &'static str,
         source: std::io::Error,
     },
 
@@ -18,9 +19,70 @@ pub enum AppError {
         source: toml::de::Error,
     },
 
+    #[error(
+        "Configuration error: watch '{watch}' could not read its paths-file {path}: {source} ({hint})"
+    )]
+    PathsFileRead {
+        watch: String,
+        path: PathBuf,
+        hint: &'static str,
+        source: std::io::Error,
+    },
+
+    #[error("Configuration error: stdin was empty, expected a TOML configuration")]
+    ConfigStdinEmpty,
+
+    #[error("Serialization Error: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("Serialization Error: {0}")]
+    TomlSerialize(#[from] toml::ser::Error),
+
+    #[error("Configuration error: {0}")]
+    ConfigValidation(String),
+
     #[error("File System Watcher Error: {0}")]
     Notify(#[from] notify::Error),
 
+    #[error("Configuration error: shell interpreter '{0}' was not found on PATH")]
+    ShellNotFound(String),
+
+    #[error("Command '{program}' refused: {reason}")]
+    CommandNotAllowed { program: String, reason: String },
+
+    #[error(
+        "Watch limit exceeded while watching {path}: the inotify watch limit was reached. \
+         Raise it with `sysctl fs.inotify.max_user_watches=524288` (persist it in /etc/sysctl.conf), \
+         or watch a smaller tree (e.g. disable `recursive` for this entry)"
+    )]
+    WatchLimitExceeded { path: PathBuf },
+
+    #[error(
+        "Permission denied while watching {path}: kadesh needs read and execute access to it. \
+         Run kadesh as a user that can read it (e.g. add that user to the owning group, such as \
+         `adm` for /var/log), grant access with an ACL (`setfacl -R -m u:<user>:rX {path}`), \
+         or give the binary CAP_DAC_READ_SEARCH"
+    )]
+    WatchPermissionDenied { path: PathBuf },
+
+    #[error(
+        "Watch cap reached: watching {path} would need about {projected} more watch(es), \
+         exceeding max-watches = {max} ({registered} already registered)"
+    )]
+    WatchCapReached {
+        path: PathBuf,
+        projected: usize,
+        registered: usize,
+        max: usize,
+    },
+
+    #[error("Watch root {path} was removed or renamed")]
+    WatchRootRemoved { path: PathBuf },
+
+    // Sent through the shutdown channel for `exit-on`; not a failure as such.
+    #[error("Exit condition met by {path}")]
+    ExitRequested { path: PathBuf, code: i32 },
+
     #[error("Event Debouncer Error: {0}")]
     Debounce(notify::Error),
 
@@ -30,6 +92,49 @@ pub enum AppError {
         source: std::io::Error,
     },
 
+    #[error("Action Execution Error: Failed to run command '{command}': {reason}")]
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        reason: String,
+    },
+
+    #[error("Action Spawn Error: Failed to start '{command}': {source} ({hint})")]
+    ActionSpawn {
+        command: String,
+        hint: &'static str,
+        source: std::io::Error,
+    },
+
+    #[error(
+        "Action Execution Error: '{command}' exceeded its total budget of {budget_ms}ms after {attempts} attempt(s)"
+    )]
+    ActionBudgetExhausted {
+        command: String,
+        attempts: u32,
+        budget_ms: u128,
+    },
+
+    #[error("Built-in Action Error: {action} failed for {path}: {source}")]
+    BuiltinAction {
+        action: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Dead-letter file {path}, line {line}: not a valid record: {source}")]
+    DeadLetterParse {
+        path: PathBuf,
+        line: usize,
+        source: serde_json::Error,
+    },
+
+    #[error("{failed} of {total} replayed action(s) failed")]
+    ReplayFailed { failed: usize, total: usize },
+
+    #[error("Destination {0} already exists and overwrite is disabled")]
+    DestinationExists(PathBuf),
+
     #[error("Path is not valid UTF-8: {0:?}")]
     PathNonUtf8(PathBuf),
 
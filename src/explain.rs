@@ -0,0 +1,313 @@
+use crate::config::{Action, Config, DebounceGroup, Filters, RouteMatch, WatchConfig};
+use crate::errors::Result;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Explanation {
+    debounce_ms: u64,
+    debounce_group: DebounceGroup,
+    watches: Vec<WatchExplanation>,
+    route_match: &'static str,
+    routes: Vec<RouteExplanation>,
+}
+
+#[derive(Serialize)]
+struct RouteExplanation {
+    when: String,
+    matches: &'static str,
+    if_path: Option<String>,
+    run: String,
+    action: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WatchExplanation {
+    index: usize,
+    name: Option<String>,
+    path: String,
+    resolved_root: Option<String>,
+    resolve_error: Option<String>,
+    recursive_mode: &'static str,
+    files_only: bool,
+    prefer_close_write: bool,
+    same_filesystem: bool,
+    path_template: Option<String>,
+    filters: FilterExplanation,
+    actions: Vec<ActionExplanation>,
+}
+
+#[derive(Serialize)]
+struct FilterExplanation {
+    event_kinds: Option<Vec<String>>,
+    extensions: Option<Vec<String>>,
+    ignore_patterns: Vec<String>,
+    ignore_hidden: bool,
+    only_hidden: bool,
+    filter_expr: Option<String>,
+    min_paths: Option<usize>,
+    max_paths: Option<usize>,
+    min_age: Option<String>,
+    max_age: Option<String>,
+    age_from: &'static str,
+    is_symlink: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ActionExplanation {
+    extension: Option<String>,
+    event: String,
+    matches: &'static str,
+    command: String,
+}
+
+pub fn explain(config: &Config, json: bool) -> Result<()> {
+    let explanation = Explanation {
+        debounce_ms: config.debounce_ms,
+        debounce_group: config.debounce_group,
+        watches: config
+            .watches
+            .iter()
+            .enumerate()
+            .map(|(index, watch)| explain_watch(index, watch))
+            .collect(),
+        route_match: match config.route_match {
+            RouteMatch::First => "first",
+            RouteMatch::All => "all",
+        },
+        routes: config
+            .routes
+            .iter()
+            .map(|route| RouteExplanation {
+                when: route.when.clone(),
+                matches: describe_event(&route.when),
+                if_path: route.if_path.as_ref().map(|glob| glob.as_str().to_string()),
+                run: route.run.clone(),
+                action: config.named_actions.get(&route.run).map(Action::describe),
+            })
+            .collect(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&explanation)?);
+    } else {
+        print_explanation(&explanation);
+    }
+    Ok(())
+}
+
+fn explain_watch(index: usize, watch: &WatchConfig) -> WatchExplanation {
+    let (resolved_root, resolve_error) = match watch.expanded_absolute_path() {
+        Ok(root) => (Some(root), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let recursive_mode = match &resolved_root {
+        Some(root) if watch.recursive && root.is_dir() => "recursive",
+        _ => "non-recursive",
+    };
+
+    // Extension-specific actions are listed first, as they take precedence.
+    let mut by_extension: Vec<(String, &Action)> = watch
+        .by_extension
+        .iter()
+        .map(|(extension, action)| (format!(".{}", extension.trim_start_matches('.')), action))
+        .collect();
+    by_extension.sort_by(|a, b| a.0.cmp(&b.0));
+
+    WatchExplanation {
+        index,
+        name: watch.name.clone(),
+        path: watch.path.clone(),
+        resolved_root: resolved_root.map(|root| root.display().to_string()),
+        resolve_error,
+        recursive_mode,
+        files_only: watch.files_only,
+        prefer_close_write: watch.prefer_close_write,
+        same_filesystem: watch.same_filesystem,
+        path_template: watch
+            .path_template
+            .as_ref()
+            .map(|template| template.as_str().to_string()),
+        filters: explain_filters(&watch.filters),
+        actions: by_extension
+            .into_iter()
+            .map(|(extension, action)| (Some(extension), action))
+            .chain(watch.actions.iter().map(|action| (None, action)))
+            .map(|(extension, action)| ActionExplanation {
+                extension,
+                event: action.event.clone(),
+                matches: describe_event(&action.event),
+                command: action.describe(),
+            })
+            .collect(),
+    }
+}
+
+fn explain_filters(filters: &Filters) -> FilterExplanation {
+    let sorted = |set: &Option<std::collections::HashSet<String>>| {
+        set.as_ref().map(|set| {
+            let mut values: Vec<String> = set.iter().cloned().collect();
+            values.sort();
+            values
+        })
+    };
+    FilterExplanation {
+        event_kinds: sorted(&filters.event_kinds),
+        extensions: sorted(&filters.extensions),
+        ignore_patterns: filters.ignore_patterns.clone(),
+        ignore_hidden: filters.ignore_hidden,
+        only_hidden: filters.only_hidden,
+        filter_expr: filters
+            .filter_expr
+            .as_ref()
+            .map(|expr| expr.source().to_string()),
+        min_paths: filters.min_paths,
+        max_paths: filters.max_paths,
+        min_age: filters.min_age.map(|age| age.to_string()),
+        max_age: filters.max_age.map(|age| age.to_string()),
+        age_from: filters.age_from.as_str(),
+        is_symlink: filters.is_symlink,
+    }
+}
+
+fn describe_event(event: &str) -> &'static str {
+    match event.to_lowercase().as_str() {
+        "any" => "every event",
+        "create" | "create_*" => "create events",
+        "modify" | "modify_*" => "modify and access events",
+        "remove" | "remove_*" => "remove events",
+        "rename" | "rename_*" => "rename events",
+        "rename_from" => "renames, from the side of the old name",
+        "rename_to" => "renames, from the side of the new name",
+        "metadata" => "metadata (permission, timestamp, ownership, xattr) changes",
+        "xattr" => "extended attribute changes, where the platform reports them",
+        "chmod" | "permissions" => "permission changes, where the platform reports them",
+        "write_done" => "files closed after writing",
+        _ => "nothing (unknown event keyword)",
+    }
+}
+
+fn print_explanation(explanation: &Explanation) {
+    match explanation.debounce_ms {
+        0 => println!("debounce: off (raw events)"),
+        ms => println!("debounce: {}ms", ms),
+    }
+    if explanation.debounce_group == DebounceGroup::FirstComponent && explanation.debounce_ms > 0 {
+        println!("debounce groups: one per top-level entry of each watch root");
+    }
+    for watch in &explanation.watches {
+        println!();
+        match &watch.name {
+            Some(name) => println!("watch #{} ({}): {}", watch.index, name, watch.path),
+            None => println!("watch #{}: {}", watch.index, watch.path),
+        }
+        match (&watch.resolved_root, &watch.resolve_error) {
+            (Some(root), _) => println!("  resolved root: {}", root),
+            (None, Some(e)) => println!("  resolved root: <unresolved: {}>", e),
+            (None, None) => println!("  resolved root: <unresolved>"),
+        }
+        println!("  mode: {}", watch.recursive_mode);
+        if watch.files_only {
+            println!("  directories: ignored (files-only)");
+        }
+        if watch.prefer_close_write {
+            println!("  writes: acted on once closed (prefer-close-write)");
+        }
+        if watch.same_filesystem {
+            println!("  other filesystems: ignored (same-filesystem)");
+        }
+        if let Some(template) = &watch.path_template {
+            println!("  paths: only {} (path-template)", template);
+        }
+
+        let filters = &watch.filters;
+        println!("  filters:");
+        println!(
+            "    event kinds: {}",
+            filters
+                .event_kinds
+                .as_ref()
+                .map_or("any".to_string(), |kinds| kinds.join(", "))
+        );
+        println!(
+            "    extensions: {}",
+            filters
+                .extensions
+                .as_ref()
+                .map_or("any".to_string(), |exts| exts.join(", "))
+        );
+        if !filters.ignore_patterns.is_empty() {
+            println!(
+                "    ignore patterns: {}",
+                filters.ignore_patterns.join(", ")
+            );
+        }
+        if filters.ignore_hidden {
+            println!("    hidden paths: ignored");
+        }
+        if filters.only_hidden {
+            println!("    hidden paths: only");
+        }
+        if let Some(expr) = &filters.filter_expr {
+            println!("    expression: {}", expr);
+        }
+        if filters.min_paths.is_some() || filters.max_paths.is_some() {
+            println!(
+                "    paths per event: min {}, max {}",
+                filters
+                    .min_paths
+                    .map_or("any".to_string(), |n| n.to_string()),
+                filters
+                    .max_paths
+                    .map_or("any".to_string(), |n| n.to_string())
+            );
+        }
+        if filters.min_age.is_some() || filters.max_age.is_some() {
+            println!(
+                "    age by {}: min {}, max {}",
+                filters.age_from,
+                filters.min_age.as_deref().unwrap_or("any"),
+                filters.max_age.as_deref().unwrap_or("any")
+            );
+        }
+        match filters.is_symlink {
+            Some(true) => println!("    symlinks only"),
+            Some(false) => println!("    symlinks ignored"),
+            None => {}
+        }
+
+        if watch.actions.is_empty() {
+            println!("  actions: none");
+        } else {
+            println!("  actions:");
+            for action in &watch.actions {
+                let scope = action
+                    .extension
+                    .as_ref()
+                    .map_or(String::new(), |ext| format!("{} files ", ext));
+                println!(
+                    "    {}on {} ({}): {}",
+                    scope, action.event, action.matches, action.command
+                );
+            }
+        }
+    }
+
+    if !explanation.routes.is_empty() {
+        println!();
+        println!("routes ({} match):", explanation.route_match);
+        for route in &explanation.routes {
+            let condition = route
+                .if_path
+                .as_ref()
+                .map_or(String::new(), |glob| format!(" if path matches {}", glob));
+            println!(
+                "  on {} ({}){}: run {} ({})",
+                route.when,
+                route.matches,
+                condition,
+                route.run,
+                route.action.as_deref().unwrap_or("unknown action")
+            );
+        }
+    }
+}
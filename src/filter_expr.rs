@@ -0,0 +1,254 @@
+use crate::config::{EVENT_KINDS, event_kind_matches, unknown_keyword};
+use notify::EventKind;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, path::Path};
+
+// A boolean filter over the predicates kadesh already understands, e.g.
+// `(ext:.rs or ext:.toml) and not path:target`. `and` binds tighter than `or`.
+//
+//   ext:.rs        path has the extension `.rs`
+//   path:target    path contains the substring `target`
+//   glob:src/**    watch-relative path matches the glob
+//   kind:create    event kind matches the keyword (same keywords as `event-kinds`)
+#[derive(Debug, Clone)]
+pub struct FilterExpr {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Predicate(Predicate),
+}
+
+#[derive(Debug, Clone)]
+enum Predicate {
+    Extension(String),
+    PathContains(String),
+    Glob(glob::Pattern),
+    Kind(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Predicate(String),
+}
+
+#[derive(Debug)]
+pub struct FilterExprError(String);
+
+impl fmt::Display for FilterExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FilterExpr {
+    pub fn parse(source: &str) -> Result<Self, FilterExprError> {
+        let tokens = tokenize(source);
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(FilterExprError(format!(
+                "unexpected {:?} in filter expression '{}'",
+                token, source
+            )));
+        }
+        Ok(FilterExpr {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, kind: EventKind, path: &Path, watch_root: &Path) -> bool {
+        self.root.eval(kind, path, watch_root)
+    }
+}
+
+impl Serialize for FilterExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilterExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        FilterExpr::parse(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Node {
+    fn eval(&self, kind: EventKind, path: &Path, watch_root: &Path) -> bool {
+        match self {
+            Node::And(lhs, rhs) => {
+                lhs.eval(kind, path, watch_root) && rhs.eval(kind, path, watch_root)
+            }
+            Node::Or(lhs, rhs) => {
+                lhs.eval(kind, path, watch_root) || rhs.eval(kind, path, watch_root)
+            }
+            Node::Not(inner) => !inner.eval(kind, path, watch_root),
+            Node::Predicate(predicate) => predicate.eval(kind, path, watch_root),
+        }
+    }
+}
+
+impl Predicate {
+    fn parse(token: &str) -> Result<Self, FilterExprError> {
+        let (name, value) = token.split_once(':').ok_or_else(|| {
+            FilterExprError(format!(
+                "expected a predicate like 'ext:.rs', found '{}'",
+                token
+            ))
+        })?;
+        if value.is_empty() {
+            return Err(FilterExprError(format!(
+                "predicate '{}' is missing a value",
+                name
+            )));
+        }
+        match name {
+            "ext" => Ok(Predicate::Extension(
+                value.trim_start_matches('.').to_string(),
+            )),
+            "path" => Ok(Predicate::PathContains(value.to_string())),
+            "glob" => glob::Pattern::new(value)
+                .map(Predicate::Glob)
+                .map_err(|e| FilterExprError(format!("invalid glob '{}': {}", value, e))),
+            "kind" => match unknown_keyword(value, EVENT_KINDS) {
+                Some(message) => Err(FilterExprError(message)),
+                None => Ok(Predicate::Kind(value.to_string())),
+            },
+            _ => Err(FilterExprError(format!(
+                "unknown predicate '{}', expected one of: ext, path, glob, kind",
+                name
+            ))),
+        }
+    }
+
+    fn eval(&self, kind: EventKind, path: &Path, watch_root: &Path) -> bool {
+        match self {
+            Predicate::Extension(ext) => path
+                .extension()
+                .and_then(|os| os.to_str())
+                .is_some_and(|e| e == ext),
+            Predicate::PathContains(needle) => path.to_str().is_some_and(|s| s.contains(needle)),
+            Predicate::Glob(pattern) => {
+                pattern.matches_path(path.strip_prefix(watch_root).unwrap_or(path))
+            }
+            Predicate::Kind(keyword) => event_kind_matches(kind, Some(path), keyword),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Predicate(word),
+                });
+            }
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Node, FilterExprError> {
+        let mut node = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, FilterExprError> {
+        let mut node = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            node = Node::And(Box::new(node), Box::new(self.parse_not()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, FilterExprError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Node::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, FilterExprError> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let node = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(node),
+                    _ => Err(FilterExprError("missing closing ')'".to_string())),
+                }
+            }
+            Some(Token::Predicate(word)) => Predicate::parse(&word).map(Node::Predicate),
+            Some(token) => Err(FilterExprError(format!(
+                "expected a predicate or '(', found {:?}",
+                token
+            ))),
+            None => Err(FilterExprError(
+                "unexpected end of filter expression".to_string(),
+            )),
+        }
+    }
+}
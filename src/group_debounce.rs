@@ -0,0 +1,101 @@
+use crate::config::{Config, DebounceGroup};
+use notify_debouncer_full::DebouncedEvent;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+// Bounds on how much a single group holds back; past either, it is released
+// even though events keep arriving.
+const MAX_HELD_EVENTS: usize = 1000;
+const MAX_HELD_WINDOWS: u32 = 10;
+
+struct Group {
+    events: Vec<DebouncedEvent>,
+    first_seen: Instant,
+    deadline: Instant,
+}
+
+// Holds events per `debounce-group` until their group has been quiet for
+// `debounce-ms`, then releases the whole group in arrival order. Repeats of an
+// event already held (same kind and paths) are dropped. The group is read from
+// the current config on every push, so a reload takes effect for new events
+// while groups already held still run out their windows.
+#[derive(Default)]
+pub struct GroupDebouncer {
+    groups: HashMap<PathBuf, Group>,
+}
+
+impl GroupDebouncer {
+    // The events to pass on now: the pushed one when it belongs to no group,
+    // or a whole group that just hit its bounds.
+    pub fn push(
+        &mut self,
+        config: &Config,
+        event: DebouncedEvent,
+        now: Instant,
+    ) -> Vec<DebouncedEvent> {
+        let window = Duration::from_millis(config.debounce_ms);
+        let key = match config.debounce_group {
+            DebounceGroup::Off => None,
+            DebounceGroup::FirstComponent => first_component(config, &event),
+        };
+        let Some(key) = key.filter(|_| !window.is_zero()) else {
+            return vec![event];
+        };
+        let group = self.groups.entry(key.clone()).or_insert_with(|| Group {
+            events: Vec::new(),
+            first_seen: now,
+            deadline: now,
+        });
+        let repeat = group
+            .events
+            .iter()
+            .any(|held| held.kind == event.kind && held.paths == event.paths);
+        if !repeat {
+            group.events.push(event);
+        }
+        group.deadline = (now + window).min(group.first_seen + window * MAX_HELD_WINDOWS);
+        if group.events.len() >= MAX_HELD_EVENTS {
+            tracing::debug!(group = %key.display(), "Debounce group is full, releasing it early");
+            return self
+                .groups
+                .remove(&key)
+                .map(|group| group.events)
+                .unwrap_or_default();
+        }
+        Vec::new()
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.groups.values().map(|group| group.deadline).min()
+    }
+
+    pub fn take_due(&mut self, now: Instant) -> Vec<DebouncedEvent> {
+        let due: Vec<PathBuf> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut events: Vec<DebouncedEvent> = due
+            .into_iter()
+            .filter_map(|key| self.groups.remove(&key))
+            .flat_map(|group| group.events)
+            .collect();
+        events.sort_by_key(|event| event.time);
+        events
+    }
+}
+
+// The top-level entry of the first watch root the event's first path lies
+// under. Events on a root itself, or outside every root, are not grouped.
+fn first_component(config: &Config, event: &DebouncedEvent) -> Option<PathBuf> {
+    let path = event.paths.first()?;
+    config.watches.iter().find_map(|watch| {
+        let root = watch.resolved_root()?;
+        let component = path.strip_prefix(root).ok()?.components().next()?;
+        Some(root.join(Path::new(component.as_os_str())))
+    })
+}
@@ -0,0 +1,28 @@
+use crate::config::Config;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tracing::info;
+
+// Events handed to processing since startup; paused and dropped ones excluded.
+static EVENTS_PROCESSED: AtomicU64 = AtomicU64::new(0);
+
+pub fn record_event() {
+    EVENTS_PROCESSED.fetch_add(1, Ordering::Relaxed);
+}
+
+// Logs a liveness line every `interval`. The count of watches follows reloads.
+pub async fn run(interval: Duration, config_rx: watch::Receiver<Arc<Config>>) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately; startup is already logged.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let watches = config_rx.borrow().watches.len();
+        let events_processed = EVENTS_PROCESSED.load(Ordering::Relaxed);
+        info!(watches, events_processed, "Still watching");
+    }
+}
@@ -0,0 +1,125 @@
+//! `history-size`: the last N events handed to processing, with the actions
+//! they started and how those ended, served as JSON by the control endpoint's
+//! `GET /history`. Entries are keyed by the same `event_id` as the event's log
+//! lines.
+
+use crate::errors::AppError;
+use notify::EventKind;
+use notify_debouncer_full::DebouncedEvent;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::SystemTime;
+
+static HISTORY: LazyLock<Mutex<VecDeque<Entry>>> = LazyLock::new(Mutex::default);
+
+#[derive(Serialize)]
+struct Entry {
+    event_id: String,
+    timestamp: String,
+    kind: EventKind,
+    paths: Vec<PathBuf>,
+    actions: Vec<ActionRecord>,
+}
+
+#[derive(Serialize)]
+struct ActionRecord {
+    watch: String,
+    action: String,
+    path: PathBuf,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+enum Outcome {
+    Running,
+    // Collected into a digest, which runs on its own later.
+    Digested,
+    Succeeded,
+    Failed { error: String },
+}
+
+// Where an action's outcome goes once it finishes.
+pub struct Slot {
+    event_id: String,
+    index: usize,
+}
+
+// The size is passed on each call so a reload can grow, shrink or disable the
+// history; 0 clears it.
+pub fn record_event(size: usize, event_id: &str, event: &DebouncedEvent) {
+    let mut history = HISTORY.lock().unwrap_or_else(PoisonError::into_inner);
+    if size == 0 {
+        history.clear();
+        return;
+    }
+    while history.len() >= size {
+        history.pop_front();
+    }
+    history.push_back(Entry {
+        event_id: event_id.to_string(),
+        timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        kind: event.kind,
+        paths: event.paths.clone(),
+        actions: Vec::new(),
+    });
+}
+
+pub fn action_started(event_id: &str, watch: &str, action: &str, path: &Path) -> Option<Slot> {
+    push_action(event_id, watch, action, path, Outcome::Running)
+}
+
+pub fn action_digested(event_id: &str, watch: &str, action: &str, path: &Path) {
+    push_action(event_id, watch, action, path, Outcome::Digested);
+}
+
+pub fn action_finished(slot: &Slot, result: &Result<(), AppError>) {
+    let mut history = HISTORY.lock().unwrap_or_else(PoisonError::into_inner);
+    // The entry may have been pushed out while the action ran.
+    let Some(record) = history
+        .iter_mut()
+        .find(|entry| entry.event_id == slot.event_id)
+        .and_then(|entry| entry.actions.get_mut(slot.index))
+    else {
+        return;
+    };
+    record.outcome = match result {
+        Ok(()) => Outcome::Succeeded,
+        Err(e) => Outcome::Failed {
+            error: e.to_string(),
+        },
+    };
+}
+
+// Oldest first.
+pub fn to_json() -> serde_json::Result<String> {
+    let history = HISTORY.lock().unwrap_or_else(PoisonError::into_inner);
+    serde_json::to_string(&*history)
+}
+
+fn push_action(
+    event_id: &str,
+    watch: &str,
+    action: &str,
+    path: &Path,
+    outcome: Outcome,
+) -> Option<Slot> {
+    let mut history = HISTORY.lock().unwrap_or_else(PoisonError::into_inner);
+    let entry = history
+        .iter_mut()
+        .rev()
+        .find(|entry| entry.event_id == event_id)?;
+    entry.actions.push(ActionRecord {
+        watch: watch.to_string(),
+        action: action.to_string(),
+        path: path.to_path_buf(),
+        outcome,
+    });
+    Some(Slot {
+        event_id: event_id.to_string(),
+        index: entry.actions.len() - 1,
+    })
+}
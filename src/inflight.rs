@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::Notify;
+
+// Actions started and not yet finished, so shutdown can wait for them.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static IDLE: Notify = Notify::const_new();
+
+pub struct Running(());
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if RUNNING.fetch_sub(1, Ordering::AcqRel) == 1 {
+            IDLE.notify_waiters();
+        }
+    }
+}
+
+// Taken before the action's task is spawned, so an action is counted from the
+// moment it is started rather than when the task first runs.
+pub fn start() -> Running {
+    RUNNING.fetch_add(1, Ordering::AcqRel);
+    Running(())
+}
+
+pub async fn wait_idle() {
+    loop {
+        // Created before checking, so a notification in between is not missed.
+        let idle = IDLE.notified();
+        if RUNNING.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        idle.await;
+    }
+}
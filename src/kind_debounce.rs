@@ -0,0 +1,61 @@
+use crate::config::event_kind_to_primary_string;
+use notify_debouncer_full::DebouncedEvent;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+type PendingKey = (&'static str, Vec<PathBuf>);
+
+// Holds events for an extra, per-category window on top of the global debounce.
+// Each new event for the same category and paths replaces the held one and
+// restarts its window, so only the last event of a burst is released.
+pub struct KindDebouncer {
+    windows: HashMap<String, Duration>,
+    pending: HashMap<PendingKey, (DebouncedEvent, Instant)>,
+}
+
+impl KindDebouncer {
+    pub fn new(windows: &HashMap<String, u64>) -> Self {
+        Self {
+            windows: windows
+                .iter()
+                .map(|(kind, ms)| (kind.to_lowercase(), Duration::from_millis(*ms)))
+                .collect(),
+            pending: HashMap::new(),
+        }
+    }
+
+    // Returns the event back when it isn't subject to a per-kind window.
+    pub fn push(&mut self, event: DebouncedEvent, now: Instant) -> Option<DebouncedEvent> {
+        let Some(category) = event_kind_to_primary_string(event.kind) else {
+            return Some(event);
+        };
+        let Some(window) = self.windows.get(category) else {
+            return Some(event);
+        };
+        let key = (category, event.paths.clone());
+        self.pending.insert(key, (event, now + *window));
+        None
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|(_, deadline)| *deadline).min()
+    }
+
+    pub fn take_due(&mut self, now: Instant) -> Vec<DebouncedEvent> {
+        let due: Vec<PendingKey> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut events: Vec<DebouncedEvent> = due
+            .into_iter()
+            .filter_map(|key| self.pending.remove(&key).map(|(event, _)| event))
+            .collect();
+        events.sort_by_key(|event| event.time);
+        events
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use crate::config::device_id;
+
+// Warn once the projected watch count reaches this share of the inotify limit.
+// Nearing it only warns: watches are not switched over to polling, so a tree
+// too large for the limit needs the limit raised or `max-watches` set.
+const WATCH_LIMIT_WARN_RATIO: f64 = 0.8;
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+// inotify watches don't use file descriptors; they count against
+// `fs.inotify.max_user_watches`, which is shared by all of the user's processes.
+pub fn inotify_watch_limit() -> Option<u64> {
+    std::fs::read_to_string(MAX_USER_WATCHES)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+pub fn near_watch_limit(projected: usize, limit: u64) -> bool {
+    projected as f64 >= limit as f64 * WATCH_LIMIT_WARN_RATIO
+}
+
+// A recursive watch registers every directory below the root, a non-recursive
+// one just the root. Symlinked directories are not followed, matching notify.
+// With `same_filesystem` the walk stops at mount points.
+pub fn projected_watches(root: &Path, recursive: bool, same_filesystem: bool) -> usize {
+    if !recursive || !root.is_dir() {
+        return 1;
+    }
+    let root_device = same_filesystem.then(|| device_id(root)).flatten();
+    let mut count = 0;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        count += 1;
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                let path = entry.path();
+                if root_device.is_some() && device_id(&path) != root_device {
+                    continue;
+                }
+                pending.push(path);
+            }
+        }
+    }
+    count
+}
@@ -0,0 +1,143 @@
+//! Per-watch log files (`log-targets` and a watch's `log-target`).
+//!
+//! Action spans carry their watch's `log-target`. Log lines emitted under such
+//! a span go to that target's file instead of stdout; everything else stays on
+//! stdout. Span close timings are only written to stdout. Each file is written synchronously under its own lock, one write per
+//! line, so busy targets add a write syscall per line to whichever task logs.
+//! Targets are set up once at startup.
+
+use crate::errors::{AppError, Result};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+pub const FIELD: &str = "log_target";
+
+// The target a span was tagged with, kept in its extensions.
+struct LogTarget(String);
+
+struct TargetVisitor(Option<String>);
+
+impl Visit for TargetVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == FIELD {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == FIELD {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+// Copies the `log_target` field of new spans into their extensions, where the
+// per-target filters can find it.
+struct Tagger;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Tagger {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = TargetVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(target), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(LogTarget(target));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut visitor = TargetVisitor(None);
+        values.record(&mut visitor);
+        if let (Some(target), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().replace(LogTarget(target));
+        }
+    }
+}
+
+// The innermost target among the current span and its parents.
+fn current_target<S: Subscriber + for<'a> LookupSpan<'a>>(ctx: &Context<'_, S>) -> Option<String> {
+    ctx.lookup_current()?.scope().find_map(|span| {
+        span.extensions()
+            .get::<LogTarget>()
+            .map(|target| target.0.clone())
+    })
+}
+
+// Spans are always let through so every layer can show them as context; only
+// events are routed.
+fn routes_to<S: Subscriber + for<'a> LookupSpan<'a>>(
+    target: Option<&str>,
+    metadata: &Metadata<'_>,
+    ctx: &Context<'_, S>,
+) -> bool {
+    metadata.is_span() || current_target(ctx).as_deref() == target
+}
+
+pub fn init(filter: EnvFilter, targets: &HashMap<String, PathBuf>) -> Result<()> {
+    let mut layers = Vec::with_capacity(targets.len() + 1);
+    layers.push(
+        tracing_subscriber::fmt::layer()
+            .with_span_events(FmtSpan::CLOSE)
+            .with_filter(tracing_subscriber::filter::dynamic_filter_fn(
+                |metadata, ctx| routes_to(None, metadata, ctx),
+            ))
+            .boxed(),
+    );
+    for (name, path) in targets {
+        let file = open(path)?;
+        let name = name.clone();
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .fmt_fields(plain_fields())
+                .with_writer(Mutex::new(file))
+                .with_filter(tracing_subscriber::filter::dynamic_filter_fn(
+                    move |metadata, ctx| routes_to(Some(&name), metadata, ctx),
+                ))
+                .boxed(),
+        );
+    }
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(Tagger)
+        .with(layers)
+        .init();
+    Ok(())
+}
+
+// Span fields are formatted once per formatter type and shared between layers,
+// so the files need a type of their own to not get stdout's ANSI colours.
+fn plain_fields() -> impl for<'writer> tracing_subscriber::fmt::FormatFields<'writer> + 'static {
+    use tracing_subscriber::field::MakeExt;
+    tracing_subscriber::fmt::format::debug_fn(|writer, field, value| {
+        if field.name() == "message" {
+            write!(writer, "{:?}", value)
+        } else {
+            write!(writer, "{}={:?}", field, value)
+        }
+    })
+    .delimited(" ")
+}
+
+fn open(path: &PathBuf) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| {
+            AppError::ConfigValidation(format!(
+                "cannot open log target file {}: {}",
+                path.display(),
+                e
+            ))
+        })
+}
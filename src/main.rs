@@ -1,18 +1,68 @@
 mod actions;
+mod allowlist;
+mod bench;
+#[cfg(feature = "broker")]
+mod broker;
+mod builtin;
+mod clock;
 mod config;
+mod control;
+mod dead_letter;
+mod dedup;
+mod digest;
+mod dir_size;
+mod emptiness;
 mod errors;
+mod explain;
+mod filter_expr;
+mod group_debounce;
+mod heartbeat;
+mod history;
+mod inflight;
+mod kind_debounce;
+mod limits;
+mod log_targets;
+mod once;
+mod ownership;
+mod pause;
+mod pool;
+mod print_events;
+mod quiet;
+mod rate_limit;
+mod reload;
+mod rescan;
+mod sampling;
+mod stability;
+mod tail;
+mod transient;
+mod watcher;
 
+use crate::actions::ActionContext;
+use crate::dedup::RecentEvents;
 use crate::errors::{AppError, Result};
+use crate::group_debounce::GroupDebouncer;
+use crate::kind_debounce::KindDebouncer;
+use crate::transient::TransientFilter;
+use crate::watcher::FsWatcher;
 
-use clap::Parser;
-use config::{WatchConfig, event_kind_to_primary_string, load_config};
-use notify::{INotifyWatcher, RecursiveMode};
-use notify_debouncer_full::{
-    DebounceEventResult, DebouncedEvent, Debouncer, NoCache, new_debouncer,
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{
+    Action, ActionKind, OverflowPolicy, PausePolicy, RootRemovedPolicy, RouteMatch, WatchConfig,
+    device_id, event_kind_matches, event_kind_to_primary_string, is_directory_event, load_config,
 };
-use std::{path::PathBuf, sync::Arc, time::Duration};
-use tokio::sync::mpsc;
-use tracing::{Instrument, debug, error, info, instrument, warn};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode};
+use notify_debouncer_full::{DebounceEventResult, DebouncedEvent};
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::{Arc, LazyLock, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{mpsc, watch};
+use tracing::{Instrument, debug, error, info, instrument, trace, warn};
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::format::FmtSpan;
 
@@ -24,63 +74,364 @@ use tracing_subscriber::fmt::format::FmtSpan;
     long_about = "Monitors file system events & triggers actions."
 )]
 struct Args {
+    /// Path to the TOML configuration file, or `-` to read it from stdin
     #[arg(short, long, value_name = "FILE", default_value = "config.toml")]
     config: PathBuf,
+
+    /// Validate the configuration, including that the shell exists, and exit
+    #[arg(long)]
+    check: bool,
+
+    /// Exit with an error as soon as any action fails (for one-shot/CI runs)
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Only allow the programs listed in this file, one per line, replacing any
+    /// `command-allowlist` in the config
+    #[arg(long, value_name = "FILE")]
+    command_allowlist: Option<PathBuf>,
+
+    /// Print every raw debounced event under DIR (watched recursively) and
+    /// nothing else. No config is read, so no filters or actions apply
+    #[arg(long, value_name = "DIR")]
+    print_events: Option<PathBuf>,
+
+    /// Debounce window for `--print-events`; 0 prints raw events
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 500,
+        requires = "print_events"
+    )]
+    debounce_ms: u64,
+
+    /// Print the fully resolved configuration and exit. Commands and env values
+    /// are printed as-is, so the output may contain secrets
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "toml")]
+    dump_config: Option<DumpFormat>,
+
+    /// Worker threads for the async runtime. Defaults to one per CPU core;
+    /// 1 or 2 are plenty unless many actions run at once
+    #[arg(long, value_name = "N", conflicts_with = "current_thread")]
+    threads: Option<NonZeroUsize>,
+
+    /// Run everything on a single thread, for the smallest footprint. Event
+    /// handling then shares that thread with all other work, so bursts of
+    /// events are worked through more slowly
+    #[arg(long)]
+    current_thread: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DumpFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print how each watch, filter and action in the config is interpreted
+    Explain {
+        /// Emit the explanation as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Generate synthetic file churn and report event throughput and latency
+    /// under the config's debounce settings
+    Bench {
+        /// Directory to churn files in; created if missing
+        #[arg(long)]
+        dir: PathBuf,
+        /// File operations per second
+        #[arg(long, default_value_t = 100)]
+        rate: u32,
+        /// How long to generate load for, in seconds
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+        /// Number of distinct files to cycle through
+        #[arg(long, default_value_t = 50)]
+        files: usize,
+    },
+    /// Re-run the actions recorded in a dead-letter file
+    Replay {
+        /// Dead-letter file written via `dead-letter-file`
+        file: PathBuf,
+    },
+}
+
+// The runtime is built by hand so its size can be chosen on the command line.
+// It can't come from the config, which is itself read on the runtime (and may
+// be stdin, which can only be read once). Actions are separate processes and
+// builtin file operations run on the blocking pool, so worker threads only
+// carry event processing and bookkeeping: a few are enough even at high event
+// rates, and the per-core default mostly costs memory in containers.
+fn main() -> Result<()> {
     let args = Args::parse();
+    let mut builder = if args.current_thread {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    if let Some(threads) = args.threads {
+        builder.worker_threads(threads.get());
+    }
+    builder.enable_all().build()?.block_on(run(args))
+}
+
+async fn run(args: Args) -> Result<()> {
+    if let Some(dir) = &args.print_events {
+        return print_events::run(dir, args.debounce_ms).await;
+    }
+
+    let command_allowlist = match &args.command_allowlist {
+        Some(file) => match allowlist::load(file) {
+            Ok(allowlist) => Some(allowlist),
+            Err(e) => {
+                eprintln!("Error loading command allowlist: {}", e);
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+    let fail_fast = args.fail_fast;
+    let overrides = move |cfg: &mut config::Config| {
+        cfg.fail_fast |= fail_fast;
+        if command_allowlist.is_some() {
+            cfg.command_allowlist.clone_from(&command_allowlist);
+        }
+    };
 
     let config = match load_config(&args.config).await {
-        Ok(cfg) => Arc::new(cfg),
+        Ok(mut cfg) => {
+            overrides(&mut cfg);
+            Arc::new(cfg)
+        }
         Err(e) => {
             eprintln!("Error loading configuration: {}", e);
 
             if let AppError::ConfigParse { path, source } = &e {
                 eprintln!(" -> Parsing error in: {:?}: {}", path, source);
-            } else if let AppError::ConfigRead { path, source } = &e {
-                eprintln!(" -> Reading error for {:?}: {}", path, source);
+            } else if let AppError::ConfigRead { path, hint, .. } = &e {
+                eprintln!(" -> Reading error for {:?}: {}", path, hint);
             }
             return Err(e);
         }
     };
 
+    if args.check {
+        if let Err(e) = config.check_shell() {
+            eprintln!("Configuration check failed: {}", e);
+            return Err(e);
+        }
+        println!("Configuration OK: {} watch(es)", config.watches.len());
+        return Ok(());
+    }
+
+    if let Some(format) = args.dump_config {
+        let dumped = match format {
+            DumpFormat::Toml => toml::to_string_pretty(config.as_ref())?,
+            DumpFormat::Json => serde_json::to_string_pretty(config.as_ref())?,
+        };
+        println!("{}", dumped);
+        return Ok(());
+    }
+
+    if let Some(Command::Explain { json }) = args.command {
+        return explain::explain(&config, json);
+    }
+
     let log_filter = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new(&config.log_level))
         .unwrap_or_else(|_| EnvFilter::new("info"));
-    tracing_subscriber::fmt()
-        .with_env_filter(log_filter)
-        .with_span_events(FmtSpan::CLOSE)
-        .init();
+    if config.log_targets.is_empty() {
+        tracing_subscriber::fmt()
+            .with_env_filter(log_filter)
+            .with_span_events(FmtSpan::CLOSE)
+            .init();
+    } else if let Err(e) = log_targets::init(log_filter, &config.log_targets) {
+        eprintln!("Error setting up log targets: {}", e);
+        return Err(e);
+    }
 
     info!("Logging initialized!");
     debug!(config = ?config, "Loaded configuration");
 
-    let (event_tx, mut event_rx) = mpsc::channel::<DebounceEventResult>(100);
-    let runtime_handle = tokio::runtime::Handle::current();
-    let mut debouncer = new_debouncer(
-        Duration::from_millis(config.debounce_ms),
-        None,
-        move |result| {
-            let tx = event_tx.clone();
-            let handle = runtime_handle.clone();
-            handle.spawn(async move {
-                if let Err(e) = tx.send(result).await {
-                    error!("Failed to send debounced event: {}", e);
-                }
-            });
-        },
-    )
-    .map_err(AppError::Debounce)?;
+    if let Err(e) = config.check_shell() {
+        warn!(error = %e, "Actions will fail to start until the shell is available");
+    }
 
-    for watch_config in &config.watches {
-        match setup_watch(&mut debouncer, watch_config) {
-            Ok(abs_path) => info!(
-              path = %abs_path.display(),
-              recursive = watch_config.recursive,
-              "Started watching"
-            ),
+    match args.command {
+        Some(Command::Replay { file }) => return dead_letter::replay(&config, &file).await,
+        Some(Command::Bench {
+            dir,
+            rate,
+            duration,
+            files,
+        }) => {
+            let options = bench::BenchOptions {
+                dir,
+                rate,
+                duration: Duration::from_secs(duration),
+                files,
+            };
+            return bench::run(&config, options).await;
+        }
+        _ => {}
+    }
+
+    let (event_tx, event_rx) = mpsc::channel::<DebounceEventResult>(EVENT_QUEUE_BATCHES);
+    let debouncer = FsWatcher::new(&config, move |result: DebounceEventResult| {
+        queue_batch(&event_tx, result)
+    })?;
+    let debouncer: SharedDebouncer = Arc::new(Mutex::new(debouncer));
+
+    register_watches(&config, &debouncer).await;
+
+    if config.watches.is_empty() {
+        warn!("No valid watch paths configured. Exiting.");
+        return Ok(());
+    }
+
+    if let Some(broker) = &config.broker {
+        #[cfg(feature = "broker")]
+        broker::spawn(broker);
+        #[cfg(not(feature = "broker"))]
+        warn!(address = %broker.address, "Broker configured but kadesh was built without the `broker` feature, events will not be published");
+    }
+
+    info!("File system monitor started. Press Ctrl+C to stop.");
+
+    let config_rx = reload::spawn_listener(args.config.clone(), Arc::clone(&config), overrides);
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<AppError>(1);
+    if let Some(addr) = &config.control_addr {
+        tokio::spawn(control::serve(
+            addr.clone(),
+            config_rx.clone(),
+            shutdown_tx.clone(),
+            Arc::clone(&debouncer),
+        ));
+    }
+    if let Some(interval_ms) = config.heartbeat_interval_ms {
+        tokio::spawn(heartbeat::run(
+            Duration::from_millis(interval_ms),
+            config_rx.clone(),
+        ));
+    }
+    if let Some(action) = &config.on_start {
+        run_lifecycle_hook(&config, action, "on-start").await;
+    }
+    let event_processor = tokio::spawn(supervise_event_processor(
+        config_rx.clone(),
+        Arc::clone(&debouncer),
+        shutdown_tx.clone(),
+        event_rx,
+    ));
+
+    let mut exit_code = 0;
+    tokio::select! {
+      _ = tokio::signal::ctrl_c() => {
+            info!("Ctrl+C received. Shutting down...");
+        }
+      _ = event_processor => {
+        warn!("Event processor task completed unexpectedly.");
+
+      }
+      Some(e) = shutdown_rx.recv() => {
+        match e {
+            AppError::ExitRequested { path, code } => {
+                info!(path = %path.display(), code, "Exit condition met, waiting for running actions");
+                inflight::wait_idle().await;
+                exit_code = code;
+            }
+            e => {
+                error!(error = %e, "Shutting down");
+                drop(debouncer);
+                return Err(e);
+            }
+        }
+      }
+    };
+
+    // Changes already collected into a digest still get their run, with the
+    // watch as currently configured, wherever a reload moved it.
+    let config = Arc::clone(&config_rx.borrow());
+    for mut digest in digest::take_all() {
+        let Some(watch_index) = config
+            .watches
+            .iter()
+            .position(|w| w.id() == digest.watch_id)
+        else {
+            warn!(action = %digest.action.describe(), "Watch of a pending digest was removed, dropping it");
+            continue;
+        };
+        digest.watch_index = watch_index;
+        run_digest(Arc::clone(&config), digest, shutdown_tx.clone()).await;
+    }
+    if let Some(action) = &config.on_stop {
+        let on_stop = run_lifecycle_hook(&config, action, "on-stop");
+        if tokio::time::timeout(ON_STOP_TIMEOUT, on_stop)
+            .await
+            .is_err()
+        {
+            warn!(timeout = ?ON_STOP_TIMEOUT, "on-stop did not finish in time, exiting anyway");
+        }
+    }
+
+    drop(debouncer);
+    info!("Watcher stopped. Exiting.");
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+// Registers the watch entries in order, up to `max-watches`: once an entry
+// would push the projected total past it, that entry and all later ones are
+// skipped. Used both at startup and when a reload changes the watches.
+async fn register_watches(config: &config::Config, debouncer: &SharedDebouncer) {
+    let projections = project_watches(config).await;
+    let projected_total: usize = projections.iter().sum();
+    if let Some(limit) = limits::inotify_watch_limit()
+        && limits::near_watch_limit(projected_total, limit)
+    {
+        warn!(
+            projected = projected_total,
+            limit,
+            "Projected number of watches is close to the inotify limit (fs.inotify.max_user_watches). \
+             Raise it with `sysctl fs.inotify.max_user_watches=<n>`, or set max-watches to stop before running out"
+        );
+    }
+
+    let mut registered = 0;
+    for (watch_config, projected) in config.watches.iter().zip(projections) {
+        if let Some(max) = config.max_watches
+            && registered + projected > max
+        {
+            let e = AppError::WatchCapReached {
+                path: PathBuf::from(&watch_config.path),
+                projected,
+                registered,
+                max,
+            };
+            error!(error = %e, "Skipping this and all remaining watch entries");
+            break;
+        }
+        match setup_watch(&mut lock_debouncer(debouncer), watch_config) {
+            Ok(abs_path) => {
+                registered += projected;
+                if watch_config.tail {
+                    tail::prime(&abs_path, watch_config.recursive);
+                }
+                info!(
+                  path = %abs_path.display(),
+                  recursive = watch_config.recursive,
+                  "Started watching"
+                )
+            }
             Err(e) => error!(
               config_path = %watch_config.path,
               error = %e,
@@ -88,25 +439,211 @@ async fn main() -> Result<()> {
             ),
         }
     }
+}
 
-    if config.watches.is_empty() {
-        warn!("No valid watch paths configured. Exiting.");
-        return Ok(());
+// How many inotify watches each entry will take. Counting a recursive entry
+// walks its whole tree, so it runs on the blocking pool.
+async fn project_watches(config: &config::Config) -> Vec<usize> {
+    let entries: Vec<_> = config
+        .watches
+        .iter()
+        .map(|watch_config| {
+            (
+                watch_config.expanded_absolute_path().ok(),
+                watch_config.recursive,
+                watch_config.same_filesystem,
+            )
+        })
+        .collect();
+    let counted = tokio::task::spawn_blocking(move || {
+        entries
+            .iter()
+            .map(|(path, recursive, same_filesystem)| match path {
+                Some(path) => limits::projected_watches(path, *recursive, *same_filesystem),
+                None => 0,
+            })
+            .collect::<Vec<_>>()
+    })
+    .await;
+    counted.unwrap_or_else(|e| {
+        warn!(error = %e, "Failed to count watches, assuming one per entry");
+        vec![1; config.watches.len()]
+    })
+}
+
+// Runs the event processor and restarts it if it panics, so a single bad event
+// doesn't stop the watcher. The receiver lives outside the processor task and
+// survives restarts.
+async fn supervise_event_processor(
+    config_rx: watch::Receiver<Arc<config::Config>>,
+    debouncer: SharedDebouncer,
+    shutdown_tx: mpsc::Sender<AppError>,
+    event_rx: mpsc::Receiver<DebounceEventResult>,
+) {
+    let event_rx = Arc::new(tokio::sync::Mutex::new(event_rx));
+    let config = Arc::clone(&config_rx.borrow());
+    let pause_rx = pause::spawn_controller(&config);
+    let started = Instant::now();
+    let mut restarts = 0;
+    loop {
+        // A restarted processor picks up whatever config was last reloaded.
+        let processor = EventProcessor::new(
+            config_rx.clone(),
+            Arc::clone(&debouncer),
+            shutdown_tx.clone(),
+            pause_rx.clone(),
+            started,
+        );
+        match tokio::spawn(processor.run(Arc::clone(&event_rx))).await {
+            Ok(()) => break,
+            Err(e) if e.is_panic() && restarts < config.processor_restart_limit => {
+                restarts += 1;
+                error!(
+                    restarts,
+                    limit = config.processor_restart_limit,
+                    "Event processor panicked, restarting"
+                );
+            }
+            Err(e) => {
+                error!(error = %e, restarts, "Event processor stopped, not restarting");
+                break;
+            }
+        }
     }
+}
 
-    info!("File system monitor started. Press Ctrl+C to stop.");
+struct EventProcessor {
+    config: Arc<config::Config>,
+    debouncer: SharedDebouncer,
+    pending_roots: PendingRoots,
+    shutdown_tx: mpsc::Sender<AppError>,
+    recent_events: RecentEvents,
+    kind_debouncer: KindDebouncer,
+    group_debouncer: GroupDebouncer,
+    transient: TransientFilter,
+    settle_until: Instant,
+    settle_dropped: usize,
+    // Last seen target of each `track-symlink` watch, keyed by watch index.
+    link_targets: HashMap<usize, Option<PathBuf>>,
+    pause_rx: watch::Receiver<bool>,
+    paused_queue: Vec<DebouncedEvent>,
+    config_rx: watch::Receiver<Arc<config::Config>>,
+    // Files found by an overflow rescan, walked off the event loop.
+    rescan_tx: mpsc::Sender<Vec<PathBuf>>,
+    rescan_rx: mpsc::Receiver<Vec<PathBuf>>,
+    rescanning: bool,
+}
+
+impl EventProcessor {
+    fn new(
+        mut config_rx: watch::Receiver<Arc<config::Config>>,
+        debouncer: SharedDebouncer,
+        shutdown_tx: mpsc::Sender<AppError>,
+        pause_rx: watch::Receiver<bool>,
+        started: Instant,
+    ) -> Self {
+        let config = Arc::clone(&config_rx.borrow_and_update());
+        let (rescan_tx, rescan_rx) = mpsc::channel(1);
+        EventProcessor {
+            recent_events: RecentEvents::new(Duration::from_millis(config.dedup_window_ms)),
+            kind_debouncer: KindDebouncer::new(&config.debounce_by_kind),
+            group_debouncer: GroupDebouncer::default(),
+            transient: TransientFilter::new(if config.ignore_transient {
+                Duration::from_millis(config.transient_window_ms)
+            } else {
+                Duration::ZERO
+            }),
+            settle_until: started + Duration::from_millis(config.startup_settle_ms),
+            settle_dropped: 0,
+            link_targets: link_targets(&config),
+            pause_rx,
+            paused_queue: Vec::new(),
+            config_rx,
+            rescan_tx,
+            rescan_rx,
+            rescanning: false,
+            config,
+            debouncer,
+            pending_roots: Arc::default(),
+            shutdown_tx,
+        }
+    }
+
+    async fn run(mut self, event_rx: Arc<tokio::sync::Mutex<mpsc::Receiver<DebounceEventResult>>>) {
+        let mut event_rx = event_rx.lock().await;
+        loop {
+            let next_flush = [
+                self.kind_debouncer.next_deadline(),
+                self.group_debouncer.next_deadline(),
+                self.transient.next_deadline(),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            let result = tokio::select! {
+                result = event_rx.recv() => match result {
+                    Some(result) => result,
+                    None => break,
+                },
+                _ = sleep_until(next_flush), if next_flush.is_some() => {
+                    let now = Instant::now();
+                    for event in self.transient.take_due(now) {
+                        self.group(event, now);
+                    }
+                    for event in self.group_debouncer.take_due(now) {
+                        self.debounce_by_kind(event, now);
+                    }
+                    for event in self.kind_debouncer.take_due(now) {
+                        self.dispatch(event);
+                    }
+                    continue;
+                }
+                Ok(()) = self.pause_rx.changed() => {
+                    if !*self.pause_rx.borrow_and_update() && !self.paused_queue.is_empty() {
+                        info!(events = self.paused_queue.len(), "Dispatching events queued while paused");
+                        for event in std::mem::take(&mut self.paused_queue) {
+                            self.dispatch(event);
+                        }
+                    }
+                    continue;
+                }
+                Some(files) = self.rescan_rx.recv() => {
+                    self.rescanning = false;
+                    for path in files {
+                        let event =
+                            notify::Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path);
+                        self.dispatch(DebouncedEvent::new(event, Instant::now()));
+                    }
+                    continue;
+                }
+                Ok(()) = self.config_rx.changed() => {
+                    let config = Arc::clone(&self.config_rx.borrow_and_update());
+                    self.reload(config).await;
+                    continue;
+                }
+            };
 
-    let config_clone = Arc::clone(&config);
-    let event_processor = tokio::spawn(async move {
-        while let Some(result) = event_rx.recv().await {
+            if LAGGED.swap(false, Ordering::Relaxed) {
+                self.handle_overflow();
+            }
             match result {
                 Ok(events) => {
                     for event in events {
-                        let cfg = Arc::clone(&config_clone);
-                        tokio::spawn(
-                            process_event(event, cfg)
-                                .instrument(tracing::info_span!("process_event")),
-                        );
+                        if event.need_rescan() {
+                            self.handle_overflow();
+                            continue;
+                        }
+                        let now = Instant::now();
+                        if self.is_settling(now) {
+                            continue;
+                        }
+                        if self.recent_events.is_duplicate(&event, now) {
+                            trace!(kind = ?event.kind, paths = ?event.paths, "Suppressed duplicate event");
+                            continue;
+                        }
+                        for event in self.transient.push(event, now) {
+                            self.group(event, now);
+                        }
                     }
                 }
                 Err(errors) => {
@@ -117,32 +654,436 @@ async fn main() -> Result<()> {
             }
         }
         info!("Event processing loop finished.");
+    }
+
+    // Watches are only re-registered when their paths or modes changed; if just
+    // filters or actions did, the debouncer is left alone so no events are lost
+    // and only the config read by new events is swapped.
+    async fn reload(&mut self, config: Arc<config::Config>) {
+        if reload::watches_changed(&self.config, &config) {
+            {
+                let mut debouncer = lock_debouncer(&self.debouncer);
+                for watch_config in &self.config.watches {
+                    let Some(root) = watch_config.resolved_root() else {
+                        continue;
+                    };
+                    let registered = if watch_config.track_symlink {
+                        root.parent().unwrap_or(root)
+                    } else {
+                        root
+                    };
+                    if let Err(e) = debouncer.unwatch(registered) {
+                        debug!(path = %registered.display(), error = %e, "Failed to unwatch path");
+                    }
+                }
+            }
+            register_watches(&config, &self.debouncer).await;
+            info!(
+                watches = config.watches.len(),
+                "Configuration reloaded, watches re-registered"
+            );
+        } else {
+            for watch_config in &config.watches {
+                let _ = watch_config.expanded_absolute_path();
+            }
+            info!("Configuration reloaded, watches unchanged");
+        }
+        self.link_targets = link_targets(&config);
+        self.config = config;
+    }
+
+    // The debouncer has already refreshed its own file ids by now; what was
+    // missed in between is unknown, so a rescan can only replay everything.
+    fn handle_overflow(&mut self) {
+        match self.config.on_overflow {
+            OverflowPolicy::Ignore => debug!("Event queue overflowed, ignoring"),
+            OverflowPolicy::Warn => {
+                warn!("Event queue overflowed, events may have been missed")
+            }
+            OverflowPolicy::Rescan if self.rescanning => {
+                debug!("Event queue overflowed during a rescan, which covers it")
+            }
+            // Walking large trees takes a while, and an overflow is when the
+            // event loop can least afford to stall, so the walk runs on the
+            // blocking pool and the files are dispatched once it is done.
+            OverflowPolicy::Rescan => {
+                warn!("Event queue overflowed, rescanning all watches");
+                let roots: Vec<(PathBuf, bool)> = self
+                    .config
+                    .watches
+                    .iter()
+                    .filter_map(|watch_config| {
+                        let root = watch_config.resolved_root()?.to_path_buf();
+                        Some((root, watch_config.recursive))
+                    })
+                    .collect();
+                let rescan_tx = self.rescan_tx.clone();
+                self.rescanning = true;
+                tokio::spawn(async move {
+                    let walked = tokio::task::spawn_blocking(move || {
+                        let mut all = Vec::new();
+                        for (root, recursive) in roots {
+                            let files = rescan::files(&root, recursive);
+                            info!(path = %root.display(), files = files.len(), "Rescanning watch");
+                            all.extend(files);
+                        }
+                        all
+                    })
+                    .await;
+                    let files = walked.unwrap_or_else(|e| {
+                        error!(error = %e, "Rescan failed");
+                        Vec::new()
+                    });
+                    let _ = rescan_tx.send(files).await;
+                });
+            }
+        }
+    }
+
+    fn is_settling(&mut self, now: Instant) -> bool {
+        if now < self.settle_until {
+            self.settle_dropped += 1;
+            return true;
+        }
+        if self.settle_dropped > 0 {
+            debug!(
+                dropped = self.settle_dropped,
+                "Startup settle period over, dropped events received during it"
+            );
+            self.settle_dropped = 0;
+        }
+        false
+    }
+
+    // The holding stages after the transient filter, in order: debounce
+    // groups, then per-kind windows.
+    fn group(&mut self, event: DebouncedEvent, now: Instant) {
+        for event in self.group_debouncer.push(&self.config, event, now) {
+            self.debounce_by_kind(event, now);
+        }
+    }
+
+    fn debounce_by_kind(&mut self, event: DebouncedEvent, now: Instant) {
+        if let Some(event) = self.kind_debouncer.push(event, now) {
+            self.dispatch(event);
+        }
+    }
+
+    fn dispatch(&mut self, event: DebouncedEvent) {
+        if *self.pause_rx.borrow() {
+            match self.config.on_pause {
+                PausePolicy::Drop => {
+                    trace!(kind = ?event.kind, paths = ?event.paths, "Paused, dropping event")
+                }
+                PausePolicy::Queue if self.paused_queue.len() < MAX_PAUSED_EVENTS => {
+                    self.paused_queue.push(event)
+                }
+                PausePolicy::Queue => debug!(
+                    limit = MAX_PAUSED_EVENTS,
+                    "Pause queue is full, dropping event"
+                ),
+            }
+            return;
+        }
+        // Everything this event leads to, including actions in spawned tasks,
+        // runs under this span so its logs can be tied together by `event_id`.
+        let event_id = correlation_id();
+        let span = tracing::info_span!("event", event_id = %event_id);
+        let _entered = span.enter();
+        heartbeat::record_event();
+        history::record_event(self.config.history_size, &event_id, &event);
+        self.check_link_targets(&event);
+        handle_root_removal(
+            &event,
+            &self.config,
+            &self.debouncer,
+            &self.pending_roots,
+            &self.config_rx,
+            &self.shutdown_tx,
+        );
+        let cfg = Arc::clone(&self.config);
+        let shutdown_tx = self.shutdown_tx.clone();
+        tokio::spawn(process_event(event, event_id, cfg, shutdown_tx).instrument(span.clone()));
+    }
+
+    // The link itself is re-read rather than trusting the event kind, since
+    // repointing may surface as remove + create, a rename, or both in one batch.
+    fn check_link_targets(&mut self, event: &DebouncedEvent) {
+        for (&index, previous) in self.link_targets.iter_mut() {
+            let watch_config = &self.config.watches[index];
+            let Some(link) = watch_config.resolved_root() else {
+                continue;
+            };
+            if !event.paths.iter().any(|p| p == link) {
+                continue;
+            }
+            let current = read_link_target(link);
+            if current == *previous {
+                continue;
+            }
+            let old_target = std::mem::replace(previous, current.clone());
+            let Some(new_target) = current else {
+                info!(link = %link.display(), "Tracked symlink was removed");
+                continue;
+            };
+            info!(
+                link = %link.display(),
+                old_target = ?old_target,
+                new_target = %new_target.display(),
+                "Tracked symlink was retargeted"
+            );
+            if let Some(action) = &watch_config.on_retarget {
+                let context = ActionContext {
+                    old_target,
+                    new_target: Some(new_target),
+                    ..ActionContext::for_event(event)
+                }
+                .for_watch(index, watch_config);
+                spawn_action(
+                    &self.config,
+                    index,
+                    action,
+                    link,
+                    context,
+                    &self.shutdown_tx,
+                );
+            }
+        }
+    }
+}
+
+fn link_targets(config: &config::Config) -> HashMap<usize, Option<PathBuf>> {
+    config
+        .watches
+        .iter()
+        .enumerate()
+        .filter(|(_, watch)| watch.track_symlink)
+        .map(|(index, watch)| (index, watch.resolved_root().and_then(read_link_target)))
+        .collect()
+}
+
+// A xorshift step over a shared state seeded from the clock: not random in any
+// strong sense, but plenty to thin out log lines without a dependency.
+fn log_sampled(rate: f64) -> bool {
+    static STATE: LazyLock<AtomicU64> = LazyLock::new(|| {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        AtomicU64::new(nanos | 1)
     });
+    if rate >= 1.0 {
+        return true;
+    }
+    let mut x = STATE.load(Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    ((x >> 11) as f64 / (1u64 << 53) as f64) < rate
+}
 
-    tokio::select! {
-      _ = tokio::signal::ctrl_c() => {
-            info!("Ctrl+C received. Shutting down...");
+fn correlation_id() -> String {
+    let mut id = uuid::Uuid::new_v4().simple().to_string();
+    id.truncate(8);
+    id
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+type SharedDebouncer = Arc<Mutex<FsWatcher>>;
+type PendingRoots = Arc<Mutex<HashSet<PathBuf>>>;
+
+const ROOT_REAPPEAR_POLL: Duration = Duration::from_secs(1);
+const ON_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+// Beyond this, events received while paused with `on-pause = "queue"` are dropped.
+const MAX_PAUSED_EVENTS: usize = 10_000;
+// Debounced batches waiting for the event processor.
+const EVENT_QUEUE_BATCHES: usize = 100;
+
+static DROPPED_BATCHES: AtomicU64 = AtomicU64::new(0);
+// Set when a batch was dropped, until the processor has reacted to it.
+static LAGGED: AtomicBool = AtomicBool::new(false);
+
+// Runs on the debouncer's thread, which must not block. When the processor
+// falls EVENT_QUEUE_BATCHES behind, further batches are dropped and counted
+// instead of piling up, and the processor treats the gap like a kernel queue
+// overflow, following `on-overflow`.
+fn queue_batch(event_tx: &mpsc::Sender<DebounceEventResult>, result: DebounceEventResult) {
+    match event_tx.try_send(result) {
+        Ok(()) => {}
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            LAGGED.store(true, Ordering::Relaxed);
+            // Warn on the first drop and then every 1000th, not for each batch.
+            let dropped = DROPPED_BATCHES.fetch_add(1, Ordering::Relaxed);
+            if dropped.is_multiple_of(1000) {
+                warn!(
+                    dropped = dropped + 1,
+                    "Event processing is falling behind, dropping event batches"
+                );
+            }
         }
-      _ = event_processor => {
-        warn!("Event processor task completed unexpectedly.");
+        // The processor has stopped; kadesh is shutting down.
+        Err(mpsc::error::TrySendError::Closed(_)) => {}
+    }
+}
 
-      }
-    };
+fn lock_debouncer(debouncer: &SharedDebouncer) -> MutexGuard<'_, FsWatcher> {
+    debouncer.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-    drop(debouncer);
-    info!("Watcher stopped. Exiting.");
+// Relative targets are resolved against the link's directory.
+fn read_link_target(link: &Path) -> Option<PathBuf> {
+    let target = std::fs::read_link(link).ok()?;
+    Some(match link.parent() {
+        Some(parent) => parent.join(target),
+        None => target,
+    })
+}
 
-    Ok(())
+fn handle_root_removal(
+    event: &DebouncedEvent,
+    config: &Arc<config::Config>,
+    debouncer: &SharedDebouncer,
+    pending_roots: &PendingRoots,
+    config_rx: &watch::Receiver<Arc<config::Config>>,
+    shutdown_tx: &mpsc::Sender<AppError>,
+) {
+    let root_gone = matches!(
+        event.kind,
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From))
+    );
+    if !root_gone {
+        return;
+    }
+
+    for watch_config in &config.watches {
+        let Some(watch_root) = watch_config.resolved_root() else {
+            continue;
+        };
+        // A tracked symlink is watched through its directory, so replacing the
+        // link loses nothing.
+        if watch_config.track_symlink || !event.paths.iter().any(|p| p == watch_root) {
+            continue;
+        }
+
+        match watch_config.on_root_removed {
+            RootRemovedPolicy::Log => warn!(
+                path = %watch_root.display(),
+                "Watch root was removed or renamed, no further events will be received for it"
+            ),
+            RootRemovedPolicy::Exit => {
+                let _ = shutdown_tx.try_send(AppError::WatchRootRemoved {
+                    path: watch_root.to_path_buf(),
+                });
+            }
+            RootRemovedPolicy::Reestablish => {
+                let newly_pending = pending_roots
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(watch_root.to_path_buf());
+                if !newly_pending {
+                    continue;
+                }
+                warn!(
+                    path = %watch_root.display(),
+                    "Watch root was removed or renamed, waiting for it to reappear"
+                );
+                let watch_root = watch_root.to_path_buf();
+                let watch_config = watch_config.clone();
+                let debouncer = Arc::clone(debouncer);
+                let pending_roots = Arc::clone(pending_roots);
+                let config_rx = config_rx.clone();
+                tokio::spawn(async move {
+                    let reappeared = root_reappeared(watch_config, &watch_root, config_rx).await;
+                    if let Some(watch_config) = reappeared {
+                        let mut debouncer = lock_debouncer(&debouncer);
+                        let _ = debouncer.unwatch(&watch_root);
+                        match setup_watch(&mut debouncer, &watch_config) {
+                            Ok(abs_path) => {
+                                info!(path = %abs_path.display(), "Re-established watch")
+                            }
+                            Err(e) => error!(
+                              config_path = %watch_config.path,
+                              error = %e,
+                              "Failed to re-establish watch"
+                            ),
+                        }
+                    }
+                    pending_roots
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .remove(&watch_root);
+                });
+            }
+        }
+    }
+}
+
+// Waits for a removed watch root to reappear and returns the watch as it is
+// configured by then. None if a reload drops the watch or stops it from being
+// re-established, or once the config channel closes on shutdown.
+async fn root_reappeared(
+    mut watch_config: WatchConfig,
+    watch_root: &Path,
+    mut config_rx: watch::Receiver<Arc<config::Config>>,
+) -> Option<WatchConfig> {
+    while !watch_root.exists() {
+        tokio::select! {
+            _ = tokio::time::sleep(ROOT_REAPPEAR_POLL) => {}
+            changed = config_rx.changed() => {
+                changed.ok()?;
+                let config = Arc::clone(&config_rx.borrow_and_update());
+                let Some(current) = config.watches.iter().find(|w| {
+                    w.id() == watch_config.id()
+                        && w.on_root_removed == RootRemovedPolicy::Reestablish
+                }) else {
+                    debug!(path = %watch_root.display(), "Watch dropped, no longer waiting for its root");
+                    return None;
+                };
+                watch_config = current.clone();
+            }
+        }
+    }
+    Some(watch_config)
 }
 
-fn setup_watch(
-    watcher: &mut Debouncer<INotifyWatcher, NoCache>,
-    watch_config: &WatchConfig,
-) -> Result<PathBuf> {
+fn setup_watch(watcher: &mut FsWatcher, watch_config: &WatchConfig) -> Result<PathBuf> {
     let path_to_watch = watch_config.expanded_absolute_path()?;
 
+    if watch_config.track_symlink {
+        let Some(link_dir) = path_to_watch.parent() else {
+            return Err(AppError::ConfigValidation(format!(
+                "track-symlink path {} has no parent directory",
+                path_to_watch.display()
+            )));
+        };
+        if path_to_watch.symlink_metadata().is_err() {
+            warn!(path = %path_to_watch.display(), "Tracked symlink does not exist yet, waiting for it to be created.");
+        }
+        watcher
+            .watch(link_dir, RecursiveMode::NonRecursive)
+            .map_err(|e| {
+                if is_permission_error(&e) {
+                    AppError::WatchPermissionDenied {
+                        path: link_dir.to_path_buf(),
+                    }
+                } else {
+                    AppError::Notify(e)
+                }
+            })?;
+        return Ok(path_to_watch);
+    }
+
     if !path_to_watch.exists() {
-        warn!(path = %path_to_watch.display(), "Watch path does not exist. It will watched if created later.");
+        if watch_config.allow_missing {
+            debug!(path = %path_to_watch.display(), "Watch path does not exist yet.");
+        } else {
+            warn!(path = %path_to_watch.display(), "Watch path does not exist. It will watched if created later.");
+        }
     } else if !path_to_watch.is_dir() && watch_config.recursive {
         warn!(path = %path_to_watch.display(), "Recurisive wathc requested on a file, treating as non-recursive.");
     }
@@ -153,63 +1094,849 @@ fn setup_watch(
         RecursiveMode::NonRecursive
     };
 
-    watcher.watch(&path_to_watch, rec_mode)?;
+    watcher.watch(&path_to_watch, rec_mode).map_err(|e| {
+        if is_watch_limit_error(&e) {
+            AppError::WatchLimitExceeded {
+                path: path_to_watch.clone(),
+            }
+        } else if is_permission_error(&e) {
+            // A recursive watch can fail on a subdirectory; name that one.
+            AppError::WatchPermissionDenied {
+                path: e.paths.first().unwrap_or(&path_to_watch).clone(),
+            }
+        } else {
+            AppError::Notify(e)
+        }
+    })?;
 
     Ok(path_to_watch)
 }
 
-#[instrument(skip(event, config), fields(kind = ?event.kind, paths = ?event.paths))]
-async fn process_event(event: DebouncedEvent, config: Arc<config::Config>) {
-    debug!("Processing event");
+fn is_permission_error(err: &notify::Error) -> bool {
+    matches!(&err.kind, notify::ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::PermissionDenied)
+}
 
-    for watch_config in &config.watches {
-        let is_relevant = event
-            .paths
-            .iter()
-            .any(|p| match watch_config.expanded_absolute_path() {
-                Ok(watch_root) => p.starts_with(&watch_root),
-                Err(_) => false,
+fn is_watch_limit_error(err: &notify::Error) -> bool {
+    const ENOSPC: i32 = 28;
+    match &err.kind {
+        notify::ErrorKind::MaxFilesWatch => true,
+        notify::ErrorKind::Io(io) => cfg!(target_os = "linux") && io.raw_os_error() == Some(ENOSPC),
+        _ => false,
+    }
+}
+
+#[instrument(skip(event, event_id, config, shutdown_tx), fields(kind = ?event.kind, paths = ?event.paths))]
+async fn process_event(
+    event: DebouncedEvent,
+    event_id: String,
+    config: Arc<config::Config>,
+    shutdown_tx: mpsc::Sender<AppError>,
+) {
+    // Sampled once per event so an event's debug lines appear all or not at all.
+    let log_event = log_sampled(config.log_sample_rate);
+    if log_event {
+        debug!("Processing event");
+    }
+
+    let context = ActionContext {
+        event_id: Some(event_id),
+        ..ActionContext::for_event(&event)
+    };
+    let mut exit_path = None;
+
+    for (watch_index, watch_config) in config.watches.iter().enumerate() {
+        let Some(watch_root) = watch_config.resolved_root() else {
+            continue;
+        };
+        let mut paths = attributed_paths(&event, watch_root);
+        if watch_config.files_only {
+            paths.retain(|path| !is_directory_event(event.kind, path));
+        }
+        if watch_config.same_filesystem
+            && let Some(root_device) = device_id(watch_root)
+        {
+            paths.retain(|path| device_id(path) == Some(root_device));
+        }
+        if let Some(template) = &watch_config.path_template {
+            paths.retain(|path| template.matches_shape(path, watch_root));
+        }
+        if let Some(quiet_ms) = watch_config.post_action_quiet_ms {
+            let quiet = Duration::from_millis(quiet_ms);
+            paths.retain(|path| {
+                let acted = quiet::is_quiet(watch_config.id(), path, quiet);
+                if acted && log_event {
+                    debug!(path = %path.display(), "Event within post-action quiet period, ignoring");
+                }
+                !acted
             });
+        }
+
+        if paths.is_empty() {
+            continue;
+        }
+
+        // Any change counts towards the size, whether or not filters accept it.
+        if let Some(limit) = watch_config.size_limit_bytes
+            && !event.kind.is_access()
+            && let Some(crossing) =
+                dir_size::check(watch_config.id(), watch_root, watch_config.recursive, limit).await
+        {
+            info!(
+                watch = watch_config.display_name(),
+                ?crossing,
+                limit,
+                "Watch size crossed its limit"
+            );
+            let hook = match crossing {
+                dir_size::Crossing::Exceeded(_) => &watch_config.on_size_exceeded,
+                dir_size::Crossing::Below(_) => &watch_config.on_size_below,
+            };
+            if let Some(action) = hook {
+                let ctx = context.for_watch(watch_index, watch_config);
+                spawn_action(&config, watch_index, action, watch_root, ctx, &shutdown_tx);
+            }
+        }
 
-        if !is_relevant {
+        if watch_config.prefer_close_write && superseded_by_close_write(event.kind) {
+            if log_event {
+                debug!("Waiting for the file to be closed after writing, ignoring");
+            }
             continue;
         }
 
-        if !watch_config.filters.matches(&event) {
-            debug!(config_path = %watch_config.path, "Event filtered out");
+        if !watch_config.filters.matches(&event, watch_root) {
+            if log_event {
+                debug!(config_path = %watch_config.path, "Event filtered out");
+            }
             continue;
         }
 
-        let primary_kind_str = event_kind_to_primary_string(event.kind);
+        if watch_config.once_per_path && event.kind.is_create() {
+            paths.retain(|path| {
+                let first = once::first_time(watch_config, path);
+                if !first && log_event {
+                    debug!(path = %path.display(), "Path was created before, ignoring (once-per-path)");
+                }
+                first
+            });
+            if paths.is_empty() {
+                continue;
+            }
+        }
+
+        if exit_path.is_none()
+            && let Some(exit_on) = &config.exit_on
+            && action_matches_event(&exit_on.event, event.kind)
+        {
+            exit_path = paths
+                .iter()
+                .find(|path| {
+                    exit_on
+                        .path
+                        .as_ref()
+                        .is_none_or(|glob| glob.matches(path, watch_root))
+                })
+                .cloned();
+        }
 
-        for action in &watch_config.actions {
-            let action_event_str = action.event.to_lowercase();
-            let mut matched = false;
+        #[cfg(feature = "broker")]
+        broker::publish(watch_index, watch_config, event.kind, &paths);
 
-            if action_event_str == "any" {
-                matched = true;
-            } else if let Some(primary_kind) = primary_kind_str {
-                if action_event_str == primary_kind {
-                    matched = true;
+        if (watch_config.on_becomes_empty.is_some() || watch_config.on_becomes_nonempty.is_some())
+            && emptiness::is_relevant(event.kind)
+        {
+            for (dir, transition) in
+                emptiness::transitions(watch_config.id(), watch_root, event.kind, &paths)
+            {
+                let hook = match transition {
+                    emptiness::Transition::BecameEmpty => &watch_config.on_becomes_empty,
+                    emptiness::Transition::BecameNonEmpty => &watch_config.on_becomes_nonempty,
+                };
+                if log_event {
+                    debug!(dir = %dir.display(), ?transition, "Directory emptiness changed");
+                }
+                if let Some(action) = hook {
+                    let ctx = context.for_watch(watch_index, watch_config);
+                    spawn_action(&config, watch_index, action, &dir, ctx, &shutdown_tx);
                 }
             }
+        }
 
-            if matched {
-                if action.command.trim().is_empty() {
-                    warn!(event = %action.event, config_path = %watch_config.path, "Action has empty command, skipping.");
+        // Waited on outside this event, so a burst of writes runs the actions
+        // once, after the last of them, and other watches are not held up.
+        if watch_config.stability_checks > 0 && !event.kind.is_remove() {
+            let watch_id = watch_config.id();
+            let checks = watch_config.stability_checks;
+            let interval = Duration::from_millis(watch_config.stability_interval_ms);
+            for path in paths {
+                if !stability::note_change(watch_id, &path) {
+                    if log_event {
+                        debug!(path = %path.display(), "Already waiting for the file to settle");
+                    }
                     continue;
                 }
-                for path in &event.paths {
-                    let cmd = action.command.clone();
-                    let p = path.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = actions::execute_action(&cmd, &p).await {
-                            error!(command = %cmd, path = %p.display(), error = %e, "Action execution failed");
+                let (config, event, context, shutdown_tx) = (
+                    Arc::clone(&config),
+                    event.clone(),
+                    context.clone(),
+                    shutdown_tx.clone(),
+                );
+                tokio::spawn(
+                    async move {
+                        if stability::settled(watch_id, &path, checks, interval).await {
+                            run_watch_actions(
+                                &config,
+                                watch_index,
+                                &event,
+                                &[path],
+                                &context,
+                                &shutdown_tx,
+                            );
                         }
-                    }.instrument(tracing::info_span!("execute_action", command = %action.command)));
+                    }
+                    .in_current_span(),
+                );
+            }
+            continue;
+        }
+
+        run_watch_actions(&config, watch_index, &event, &paths, &context, &shutdown_tx);
+    }
+
+    // Checked once every watch has started its actions for this event, so
+    // shutdown can wait for them.
+    if let (Some(path), Some(exit_on)) = (exit_path, &config.exit_on) {
+        let _ = shutdown_tx
+            .send(AppError::ExitRequested {
+                path,
+                code: exit_on.code,
+            })
+            .await;
+    }
+}
+
+// Runs a watch's actions, by-extension actions and the routes for `paths`,
+// which have already been through the watch's filters.
+fn run_watch_actions(
+    config: &Arc<config::Config>,
+    watch_index: usize,
+    event: &DebouncedEvent,
+    paths: &[PathBuf],
+    context: &ActionContext,
+    shutdown_tx: &mpsc::Sender<AppError>,
+) {
+    let watch_config = &config.watches[watch_index];
+    let Some(watch_root) = watch_config.resolved_root() else {
+        return;
+    };
+    let runnable = |action: &Action| {
+        if action.kind != ActionKind::Command {
+            return true;
+        }
+        match action.shell_source(Some(event.kind)) {
+            None => false,
+            Some(source) if source.trim().is_empty() => {
+                warn!(event = %action.event, config_path = %watch_config.path, "Action has empty command, skipping.");
+                false
+            }
+            Some(_) => true,
+        }
+    };
+
+    // Followed files run the first matching action once per appended line
+    // instead of once per event. Routes and `by-extension` do not apply.
+    if watch_config.tail {
+        for path in paths {
+            if event.kind.is_remove() {
+                tail::forget(path);
+                continue;
+            }
+            let Some(action) = watch_config
+                .actions
+                .iter()
+                .find(|action| action_matches_event(&action.event, event.kind))
+                .filter(|action| runnable(action))
+            else {
+                continue;
+            };
+            for line in tail::read_new_lines(path) {
+                let ctx = ActionContext {
+                    line: Some(line),
+                    ..context.for_path(watch_index, watch_config, path)
+                };
+                spawn_action(config, watch_index, action, path, ctx, shutdown_tx);
+            }
+        }
+        return;
+    }
+
+    let mut fallback_paths = Vec::new();
+    for path in paths {
+        match watch_config.extension_action(path) {
+            Some(action) if action_matches_event(&action.event, event.kind) => {
+                if runnable(action) {
+                    let ctx = context.for_path(watch_index, watch_config, path);
+                    spawn_action(config, watch_index, action, path, ctx, shutdown_tx);
                 }
-                break;
             }
+            _ => fallback_paths.push(path),
+        }
+    }
+
+    for action in &watch_config.actions {
+        if fallback_paths.is_empty() {
+            break;
+        }
+        if !action_matches_event(&action.event, event.kind) || !runnable(action) {
+            continue;
+        }
+        for path in &fallback_paths {
+            let ctx = context.for_path(watch_index, watch_config, path);
+            spawn_action(config, watch_index, action, path, ctx, shutdown_tx);
+        }
+        break;
+    }
+
+    for route in &config.routes {
+        if !action_matches_event(&route.when, event.kind) {
+            continue;
+        }
+        let Some(action) = config.named_actions.get(&route.run) else {
+            continue;
+        };
+        let mut routed = false;
+        for path in paths {
+            if route
+                .if_path
+                .as_ref()
+                .is_some_and(|glob| !glob.matches(path, watch_root))
+            {
+                continue;
+            }
+            routed = true;
+            let ctx = context.for_path(watch_index, watch_config, path);
+            spawn_action(config, watch_index, action, path, ctx, shutdown_tx);
+        }
+        if routed && config.route_match == RouteMatch::First {
+            break;
+        }
+    }
+}
+
+fn action_matches_event(action_event: &str, kind: EventKind) -> bool {
+    let action_event = action_event.to_lowercase();
+    match action_event.as_str() {
+        "any" => true,
+        "rename" | "rename_from" | "rename_to" | "metadata" | "xattr" | "chmod" | "permissions"
+        | "write_done" => event_kind_matches(kind, None, &action_event),
+        wildcard if wildcard.ends_with("_*") => event_kind_matches(kind, None, wildcard),
+        _ => event_kind_to_primary_string(kind) == Some(action_event.as_str()),
+    }
+}
+
+fn spawn_action(
+    config: &Arc<config::Config>,
+    watch_index: usize,
+    action: &Action,
+    path: &Path,
+    context: ActionContext,
+    shutdown_tx: &mpsc::Sender<AppError>,
+) {
+    let watch_id = config.watches[watch_index].id();
+    if !sampling::admit(watch_id, action, path, Instant::now()) {
+        debug!(action = %action.describe(), path = %path.display(), "Event dropped by sample-rate/throttle-ms");
+        return;
+    }
+    let watch_name = config.watches[watch_index].display_name();
+    if let Some(interval_ms) = action.digest_interval_ms {
+        if let Some(event_id) = &context.event_id {
+            history::action_digested(event_id, watch_name, &action.describe(), path);
+        }
+        if digest::add(watch_index, watch_id, action, path, &context) {
+            let cfg = Arc::clone(config);
+            let act = action.clone();
+            let shutdown_tx = shutdown_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+                if let Some(digest) = digest::take(watch_id, &act) {
+                    run_digest(cfg, digest, shutdown_tx).await;
+                }
+            });
+        }
+        return;
+    }
+    let delay = match config.max_actions_per_second {
+        Some(per_second) => match rate_limit::reserve(per_second, config.on_rate_limit) {
+            Some(delay) => delay,
+            None => {
+                debug!(action = %action.describe(), path = %path.display(), "Action dropped by max-actions-per-second");
+                return;
+            }
+        },
+        None => Duration::ZERO,
+    };
+    let slot = context.event_id.as_deref().and_then(|event_id| {
+        history::action_started(event_id, watch_name, &action.describe(), path)
+    });
+    let act = action.clone();
+    let p = path.to_path_buf();
+    let cfg = Arc::clone(config);
+    let shutdown_tx = shutdown_tx.clone();
+    let running = inflight::start();
+    let span = action_span(config, watch_index, action);
+    tokio::spawn(
+        async move {
+            let _running = running;
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            run_action(cfg, watch_index, act, p, context, slot, shutdown_tx).await
+        }
+        .instrument(span),
+    );
+}
+
+// Tagged with the watch's `log-target`, if any, so the action's logs go there.
+fn action_span(config: &config::Config, watch_index: usize, action: &Action) -> tracing::Span {
+    let span = tracing::info_span!(
+        "execute_action",
+        action = %action.describe(),
+        log_target = tracing::field::Empty
+    );
+    if let Some(target) = &config.watches[watch_index].log_target {
+        span.record(log_targets::FIELD, target.as_str());
+    }
+    span
+}
+
+// `on-start` and `on-stop` belong to no event or file, so they run on an empty
+// path with the first watch's settings, much like control endpoint requests.
+// Validation rejects them without a watch.
+async fn run_lifecycle_hook(config: &config::Config, action: &Action, name: &str) {
+    let Some(watch) = config.watches.first() else {
+        warn!(
+            hook = name,
+            "No watch to take settings from, not running the lifecycle hook"
+        );
+        return;
+    };
+    info!(hook = name, action = %action.describe(), "Running lifecycle hook");
+    let result = actions::executor()
+        .execute(
+            config,
+            watch,
+            action,
+            Path::new(""),
+            &ActionContext::default(),
+        )
+        .await;
+    if let Err(e) = result {
+        error!(hook = name, error = %e, "Lifecycle hook failed");
+    }
+}
+
+// A digest runs once for its whole window, with the watch root as `{}` and the
+// collected paths in `{paths}` and the manifest file.
+async fn run_digest(
+    config: Arc<config::Config>,
+    digest: digest::Digest,
+    shutdown_tx: mpsc::Sender<AppError>,
+) {
+    let root = config.watches[digest.watch_index]
+        .resolved_root()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    info!(action = %digest.action.describe(), paths = digest.paths.len(), "Running digest");
+    let span = action_span(&config, digest.watch_index, &digest.action);
+    let context = ActionContext {
+        paths: digest.paths,
+        ..digest.context
+    };
+    run_action(
+        config,
+        digest.watch_index,
+        digest.action,
+        root,
+        context,
+        None,
+        shutdown_tx,
+    )
+    .instrument(span)
+    .await;
+}
+
+async fn run_action(
+    cfg: Arc<config::Config>,
+    watch_index: usize,
+    act: Action,
+    p: PathBuf,
+    context: ActionContext,
+    slot: Option<history::Slot>,
+    shutdown_tx: mpsc::Sender<AppError>,
+) {
+    let watch = &cfg.watches[watch_index];
+    let result = actions::executor()
+        .execute(&cfg, watch, &act, &p, &context)
+        .await;
+    if let Some(quiet_ms) = watch.post_action_quiet_ms {
+        quiet::record(watch.id(), &p, Duration::from_millis(quiet_ms));
+    }
+    if let Some(slot) = &slot {
+        history::action_finished(slot, &result);
+    }
+    if let Err(e) = result {
+        error!(action = %act.describe(), path = %p.display(), error = %e, "Action execution failed");
+        if let Some(file) = &cfg.dead_letter_file {
+            let record = dead_letter::DeadLetter::new(&act, &p, &context, &e);
+            if let Err(write_error) = dead_letter::append(file, &record) {
+                error!(file = %file.display(), error = %write_error, "Failed to write dead-letter record");
+            }
+        }
+        if cfg.fail_fast {
+            let _ = shutdown_tx.try_send(e);
+        }
+    }
+}
+
+// With `prefer-close-write` the close is what counts as a change. Renames,
+// metadata changes, creates and removes are kept.
+fn superseded_by_close_write(kind: EventKind) -> bool {
+    match kind {
+        EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => true,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => false,
+        EventKind::Access(_) => true,
+        _ => false,
+    }
+}
+
+// `Path::starts_with` compares whole components, so `/data/app2/x` is not
+// attributed to a watch on `/data/app`.
+fn attributed_paths(event: &DebouncedEvent, watch_root: &Path) -> Vec<PathBuf> {
+    // A stitched rename belongs to the watch holding its destination; the watch
+    // holding only the source sees the file leave its tree.
+    if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+        (event.kind, event.paths.as_slice())
+    {
+        return if to.starts_with(watch_root) {
+            vec![to.clone()]
+        } else if from.starts_with(watch_root) {
+            vec![from.clone()]
+        } else {
+            Vec::new()
+        };
+    }
+
+    event
+        .paths
+        .iter()
+        .filter(|p| p.starts_with(watch_root))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: EventKind, paths: &[&str]) -> DebouncedEvent {
+        let event = paths.iter().fold(notify::Event::new(kind), |event, path| {
+            event.add_path(PathBuf::from(path))
+        });
+        DebouncedEvent::new(event, Instant::now())
+    }
+
+    #[test]
+    fn rename_across_watches_goes_to_each_side() {
+        let rename = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/data/in/report.txt", "/data/out/report.txt"],
+        );
+        assert_eq!(
+            attributed_paths(&rename, Path::new("/data/out")),
+            [PathBuf::from("/data/out/report.txt")]
+        );
+        assert_eq!(
+            attributed_paths(&rename, Path::new("/data/in")),
+            [PathBuf::from("/data/in/report.txt")]
+        );
+        assert!(attributed_paths(&rename, Path::new("/elsewhere")).is_empty());
+    }
+
+    #[test]
+    fn sibling_roots_sharing_a_prefix_are_kept_apart() {
+        let created = event(
+            EventKind::Create(notify::event::CreateKind::File),
+            &["/data/app2/x"],
+        );
+        assert!(attributed_paths(&created, Path::new("/data/app")).is_empty());
+        assert_eq!(attributed_paths(&created, Path::new("/data/app2")).len(), 1);
+
+        let rename = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/data/app/x", "/data/app2/x"],
+        );
+        assert_eq!(
+            attributed_paths(&rename, Path::new("/data/app")),
+            [PathBuf::from("/data/app/x")]
+        );
+        assert_eq!(
+            attributed_paths(&rename, Path::new("/data/app2")),
+            [PathBuf::from("/data/app2/x")]
+        );
+    }
+
+    #[test]
+    fn rename_within_a_watch_is_attributed_to_the_destination() {
+        let rename = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/data/in/.report.txt.tmp", "/data/in/report.txt"],
+        );
+        assert_eq!(
+            attributed_paths(&rename, Path::new("/data/in")),
+            [PathBuf::from("/data/in/report.txt")]
+        );
+    }
+
+    #[test]
+    fn rename_halves_go_to_the_watch_holding_their_path() {
+        let from = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &["/data/in/report.txt"],
+        );
+        let to = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            &["/data/out/report.txt"],
+        );
+        assert_eq!(attributed_paths(&from, Path::new("/data/in")).len(), 1);
+        assert!(attributed_paths(&from, Path::new("/data/out")).is_empty());
+        assert_eq!(attributed_paths(&to, Path::new("/data/out")).len(), 1);
+        assert!(attributed_paths(&to, Path::new("/data/in")).is_empty());
+    }
+
+    #[test]
+    fn actions_can_pick_a_side_of_a_rename() {
+        let name = |mode| EventKind::Modify(ModifyKind::Name(mode));
+        assert!(action_matches_event("rename_from", name(RenameMode::From)));
+        assert!(!action_matches_event("rename_from", name(RenameMode::To)));
+        assert!(action_matches_event("RENAME_TO", name(RenameMode::To)));
+        assert!(action_matches_event("rename_to", name(RenameMode::Both)));
+    }
+
+    #[test]
+    fn metadata_actions_only_fire_on_metadata_events() {
+        use notify::event::{DataChange, MetadataKind};
+        let xattr = EventKind::Modify(ModifyKind::Metadata(MetadataKind::Extended));
+        let write = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        assert!(action_matches_event("xattr", xattr));
+        assert!(action_matches_event("metadata", xattr));
+        assert!(!action_matches_event("xattr", write));
+        assert!(!action_matches_event("metadata", write));
+    }
+
+    #[test]
+    fn chmod_actions_only_fire_on_permission_changes() {
+        use notify::event::{DataChange, MetadataKind};
+        let chmod = EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions));
+        let chown = EventKind::Modify(ModifyKind::Metadata(MetadataKind::Ownership));
+        let write = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        assert!(action_matches_event("chmod", chmod));
+        assert!(action_matches_event("permissions", chmod));
+        assert!(!action_matches_event("chmod", chown));
+        assert!(!action_matches_event("chmod", write));
+        assert!(action_matches_event("modify", chmod));
+    }
+
+    #[tokio::test]
+    async fn a_burst_of_writes_waited_on_for_stability_runs_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("fired.log");
+        let watched = dir.path().join("in");
+        std::fs::create_dir(&watched).unwrap();
+        let config: config::Config = toml::from_str(&format!(
+            r#"
+            [[watch]]
+            path = "{}"
+            stability-checks = 2
+            stability-interval-ms = 50
+            [[watch.actions]]
+            event = "modify"
+            command = "echo fired >> {}"
+            "#,
+            watched.display(),
+            log.display()
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        config.watches[0].expanded_absolute_path().unwrap();
+        let config = Arc::new(config);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+
+        let upload = watched.join("upload.bin");
+        let upload_str = upload.to_str().unwrap();
+        for chunk in 0..5 {
+            std::fs::write(&upload, "x".repeat(chunk + 1)).unwrap();
+            let write = event(EventKind::Modify(ModifyKind::Any), &[upload_str]);
+            process_event(
+                write,
+                correlation_id(),
+                Arc::clone(&config),
+                shutdown_tx.clone(),
+            )
+            .await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "fired\n");
+    }
+
+    fn resolved(toml: &str) -> Arc<config::Config> {
+        let config: config::Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        for watch_config in &config.watches {
+            watch_config.expanded_absolute_path().unwrap();
+        }
+        Arc::new(config)
+    }
+
+    #[tokio::test]
+    async fn waiting_for_a_root_ends_with_its_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("gone");
+        std::fs::create_dir(&root).unwrap();
+        let watch = |path: &Path| {
+            format!(
+                "[[watch]]\npath = \"{}\"\non-root-removed = \"reestablish\"\n",
+                path.display()
+            )
+        };
+        let old = resolved(&watch(&root));
+        let root = old.watches[0].resolved_root().unwrap().to_path_buf();
+        std::fs::remove_dir(&root).unwrap();
+
+        // A reload without the watch stops the wait.
+        let (config_tx, config_rx) = watch::channel(Arc::clone(&old));
+        let waiting = tokio::spawn({
+            let (watch_config, root) = (old.watches[0].clone(), root.clone());
+            async move { root_reappeared(watch_config, &root, config_rx).await }
+        });
+        config_tx.send(resolved(&watch(dir.path()))).unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(5), waiting).await;
+        assert!(stopped.unwrap().unwrap().is_none());
+
+        // So does shutdown closing the config channel.
+        let (config_tx, config_rx) = watch::channel(Arc::clone(&old));
+        let waiting = tokio::spawn({
+            let (watch_config, root) = (old.watches[0].clone(), root.clone());
+            async move { root_reappeared(watch_config, &root, config_rx).await }
+        });
+        drop(config_tx);
+        let stopped = tokio::time::timeout(Duration::from_secs(5), waiting).await;
+        assert!(stopped.unwrap().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reload_stops_at_max_watches_like_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        let watch = |path: &Path| format!("[[watch]]\npath = \"{}\"\n", path.display());
+        let old = resolved(&watch(&a));
+        let new = resolved(&format!("max-watches = 1\n{}{}", watch(&a), watch(&b)));
+
+        let debouncer: SharedDebouncer = Arc::new(Mutex::new(
+            FsWatcher::new(&old, |_: DebounceEventResult| {}).unwrap(),
+        ));
+        register_watches(&old, &debouncer).await;
+        let (_config_tx, config_rx) = watch::channel(old);
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let (_pause_tx, pause_rx) = watch::channel(false);
+        let mut processor = EventProcessor::new(
+            config_rx,
+            Arc::clone(&debouncer),
+            shutdown_tx,
+            pause_rx,
+            Instant::now(),
+        );
+        processor.reload(new).await;
+
+        let watched: Vec<_> = lock_debouncer(&debouncer)
+            .watched_paths()
+            .into_iter()
+            .map(|watched| watched.path)
+            .collect();
+        assert_eq!(watched, [a.canonicalize().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn an_action_touching_its_own_file_does_not_retrigger_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("fired.log");
+        let watched = dir.path().join("in");
+        std::fs::create_dir(&watched).unwrap();
+        let config = resolved(&format!(
+            r#"
+            [[watch]]
+            path = "{}"
+            post-action-quiet-ms = 5000
+            [[watch.actions]]
+            event = "modify"
+            command = "echo fired >> {}; touch {{}}"
+            "#,
+            watched.display(),
+            log.display()
+        ));
+        let (shutdown_tx, _shutdown_rx) = mpsc::channel(1);
+        let report = watched.join("report.txt");
+        std::fs::write(&report, "data").unwrap();
+        let modified = || {
+            event(
+                EventKind::Modify(ModifyKind::Any),
+                &[report.to_str().unwrap()],
+            )
+        };
+
+        process_event(
+            modified(),
+            correlation_id(),
+            Arc::clone(&config),
+            shutdown_tx.clone(),
+        )
+        .await;
+        // The quiet period starts once the action has finished.
+        while !log.exists() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        process_event(
+            modified(),
+            correlation_id(),
+            Arc::clone(&config),
+            shutdown_tx,
+        )
+        .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "fired\n");
+    }
+
+    #[test]
+    fn a_full_queue_drops_batches_and_flags_the_lag() {
+        let (event_tx, mut event_rx) = mpsc::channel(2);
+        let dropped = DROPPED_BATCHES.load(Ordering::Relaxed);
+        // However far the processor falls behind, the queue holds no more
+        // than its capacity and nothing else is left waiting to be sent.
+        for _ in 0..10_000 {
+            queue_batch(&event_tx, Ok(Vec::new()));
         }
+        assert_eq!(event_rx.len(), 2);
+        assert!(DROPPED_BATCHES.load(Ordering::Relaxed) >= dropped + 9_998);
+        assert!(event_rx.try_recv().is_ok());
+        assert!(event_rx.try_recv().is_ok());
+        assert!(event_rx.try_recv().is_err());
+        assert!(LAGGED.swap(false, Ordering::Relaxed));
+
+        drop(event_rx);
+        queue_batch(&event_tx, Ok(Vec::new()));
+        assert!(!LAGGED.load(Ordering::Relaxed));
     }
 }
@@ -0,0 +1,143 @@
+//! `once-per-path`: a watch acts on the first create of each path only, and
+//! ignores the path being created again later.
+//!
+//! Seen paths are kept in memory and, with `once-per-path-file`, appended to
+//! that file so they survive restarts. The file is read back the first time
+//! the watch sees an event. Entries older than `once-per-path-ttl-ms` are
+//! forgotten, as are paths passed to the control endpoint's `POST /forget`,
+//! which rewrites the file without them.
+
+use crate::config::WatchConfig;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+#[derive(Default)]
+struct Seen {
+    paths: HashMap<(u64, PathBuf), SystemTime>,
+    // Files read back so far, per watch. A reload may point a watch at
+    // another file, which is then read as well.
+    loaded: HashSet<(u64, PathBuf)>,
+}
+
+static SEEN: LazyLock<Mutex<Seen>> = LazyLock::new(Mutex::default);
+
+// Records `path` and returns true if the watch has not seen it before (or its
+// entry expired).
+pub fn first_time(watch: &WatchConfig, path: &Path) -> bool {
+    let watch_id = watch.id();
+    let now = SystemTime::now();
+    let ttl = watch.once_per_path_ttl_ms.map(Duration::from_millis);
+    let mut seen = SEEN.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(file) = &watch.once_per_path_file
+        && seen.loaded.insert((watch_id, file.clone()))
+    {
+        for (path, at) in load(file) {
+            seen.paths.insert((watch_id, path), at);
+        }
+    }
+    let key = (watch_id, path.to_path_buf());
+    if let Some(at) = seen.paths.get(&key)
+        && ttl.is_none_or(|ttl| now.duration_since(*at).unwrap_or_default() < ttl)
+    {
+        return false;
+    }
+    seen.paths.insert(key, now);
+    if let Some(file) = &watch.once_per_path_file
+        && let Err(e) = append(file, path, now)
+    {
+        warn!(file = %file.display(), error = %e, "Failed to record seen path");
+    }
+    true
+}
+
+// Returns whether the path had been seen.
+pub fn forget(watch: &WatchConfig, path: &Path) -> bool {
+    let watch_id = watch.id();
+    let mut seen = SEEN.lock().unwrap_or_else(PoisonError::into_inner);
+    let removed = seen.paths.remove(&(watch_id, path.to_path_buf())).is_some();
+    if removed && let Some(file) = &watch.once_per_path_file {
+        let remaining: Vec<(&Path, SystemTime)> = seen
+            .paths
+            .iter()
+            .filter(|((id, _), _)| *id == watch_id)
+            .map(|((_, path), at)| (path.as_path(), *at))
+            .collect();
+        if let Err(e) = rewrite(file, &remaining) {
+            warn!(file = %file.display(), error = %e, "Failed to rewrite seen paths");
+        }
+    }
+    removed
+}
+
+// One `<unix millis>\t<path>` line per path. Later lines win, and unreadable
+// ones are skipped.
+fn load(file: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let Ok(content) = std::fs::read_to_string(file) else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let (millis, path) = line.split_once('\t')?;
+            let at = UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?);
+            Some((PathBuf::from(path), at))
+        })
+        .collect()
+}
+
+fn line(path: &Path, at: SystemTime) -> String {
+    let millis = at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("{}\t{}\n", millis, path.display())
+}
+
+fn append(file: &Path, path: &Path, at: SystemTime) -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?
+        .write_all(line(path, at).as_bytes())
+}
+
+fn rewrite(file: &Path, paths: &[(&Path, SystemTime)]) -> std::io::Result<()> {
+    let content: String = paths.iter().map(|(path, at)| line(path, *at)).collect();
+    std::fs::write(file, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn watch(file: &Path) -> WatchConfig {
+        let toml = format!(
+            "[[watch]]\npath = \"/srv/in\"\nonce-per-path-file = \"{}\"\n",
+            file.display()
+        );
+        let mut config: Config = toml::from_str(&toml).unwrap();
+        config.watches.remove(0)
+    }
+
+    #[test]
+    fn a_watch_pointed_at_another_file_reads_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        let seen = Path::new("/srv/in/seen-before");
+        std::fs::write(&second, line(seen, SystemTime::now())).unwrap();
+
+        let before = watch(&first);
+        assert!(first_time(&before, Path::new("/srv/in/new")));
+        // As after a reload that kept the watch but changed its file.
+        let after = watch(&second);
+        after.inherit_id(&before);
+        assert!(!first_time(&after, seen));
+    }
+}
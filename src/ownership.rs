@@ -0,0 +1,155 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::Path;
+
+// A user or group given in the config by name or numeric id. Names are resolved
+// once, when the config is loaded.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct UserId(pub u32);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct GroupId(pub u32);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NameOrId {
+    Id(u32),
+    Name(String),
+}
+
+impl<'de> Deserialize<'de> for UserId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        resolve(NameOrId::deserialize(deserializer)?, "user", lookup_user)
+            .map(UserId)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for GroupId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        resolve(NameOrId::deserialize(deserializer)?, "group", lookup_group)
+            .map(GroupId)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn resolve(value: NameOrId, what: &str, lookup: fn(&str) -> Option<u32>) -> Result<u32, String> {
+    match value {
+        NameOrId::Id(id) => Ok(id),
+        NameOrId::Name(name) => match name.parse() {
+            Ok(id) => Ok(id),
+            Err(_) if !cfg!(unix) => Err(format!("{} names are only supported on Unix", what)),
+            Err(_) => lookup(&name).ok_or_else(|| format!("unknown {} '{}'", what, name)),
+        },
+    }
+}
+
+#[cfg(unix)]
+pub fn lookup_user(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: passwd is plain data; getpwnam_r fills it, pointing its strings
+    // into `buf`, which outlives every use of the result.
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    (rc == 0 && !result.is_null()).then_some(entry.pw_uid)
+}
+
+#[cfg(unix)]
+pub fn lookup_group(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: as in `lookup_user`.
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    (rc == 0 && !result.is_null()).then_some(entry.gr_gid)
+}
+
+// The user's primary group, from its passwd entry.
+#[cfg(unix)]
+pub fn primary_group(uid: u32) -> Option<u32> {
+    // SAFETY: as in `lookup_user`.
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+    (rc == 0 && !result.is_null()).then_some(entry.pw_gid)
+}
+
+// Who an action runs as. Without a user only the group changes.
+#[derive(Debug, Clone, Copy)]
+pub struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: u32,
+}
+
+// Called between fork and exec, so it only makes async-signal-safe calls. The
+// order matters: supplementary groups and the gid can only be changed while
+// still root, so the uid goes last. Any failure aborts the spawn rather than
+// letting the command run with kadesh's own privileges.
+#[cfg(unix)]
+pub fn drop_privileges(credentials: Credentials) -> std::io::Result<()> {
+    // SAFETY: plain syscalls on values owned by this frame.
+    unsafe {
+        if libc::setgroups(1, &credentials.gid) != 0 || libc::setgid(credentials.gid) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if let Some(uid) = credentials.uid
+            && libc::setuid(uid) != 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lookup_user(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn lookup_group(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+pub fn primary_group(_uid: u32) -> Option<u32> {
+    None
+}
+
+// Ownership is read without following symlinks. Paths that cannot be stat'ed
+// (already gone, or unreadable) never match.
+#[cfg(unix)]
+pub fn owned_by(path: &Path, owner: Option<UserId>, group: Option<GroupId>) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let Ok(metadata) = path.symlink_metadata() else {
+        return false;
+    };
+    owner.is_none_or(|owner| metadata.uid() == owner.0)
+        && group.is_none_or(|group| metadata.gid() == group.0)
+}
+
+#[cfg(not(unix))]
+pub fn owned_by(_path: &Path, _owner: Option<UserId>, _group: Option<GroupId>) -> bool {
+    true
+}
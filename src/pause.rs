@@ -0,0 +1,71 @@
+use crate::config::Config;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::info;
+
+const PAUSE_FILE_POLL: Duration = Duration::from_secs(1);
+
+// Dispatch is paused while the pause file exists or after a SIGUSR1, until the
+// file is gone and a second SIGUSR1 has been received. The receiver reports the
+// combined state; it never changes if neither source is available.
+pub fn spawn_controller(config: &Config) -> watch::Receiver<bool> {
+    let (pause_tx, pause_rx) = watch::channel(false);
+    let pause_file = config.pause_file.clone();
+    tokio::spawn(async move {
+        let mut signal = toggle_signal();
+        let mut poll = tokio::time::interval(PAUSE_FILE_POLL);
+        let (mut by_signal, mut by_file) = (false, false);
+        loop {
+            tokio::select! {
+                Some(()) = recv(&mut signal) => by_signal = !by_signal,
+                _ = poll.tick(), if pause_file.is_some() => {
+                    by_file = pause_file.as_ref().is_some_and(|file| file.exists());
+                }
+            }
+            let paused = by_signal || by_file;
+            if paused != *pause_tx.borrow() {
+                if paused {
+                    info!(by_signal, by_file, "Paused, actions are suppressed");
+                } else {
+                    info!("Resumed");
+                }
+                if pause_tx.send(paused).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    pause_rx
+}
+
+#[cfg(unix)]
+type ToggleSignal = Option<tokio::signal::unix::Signal>;
+#[cfg(not(unix))]
+type ToggleSignal = Option<()>;
+
+fn toggle_signal() -> ToggleSignal {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::user_defined1()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                tracing::warn!(error = %e, "Cannot listen for SIGUSR1, pausing by signal is unavailable");
+                None
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+async fn recv(signal: &mut ToggleSignal) -> Option<()> {
+    #[cfg(unix)]
+    if let Some(signal) = signal {
+        return signal.recv().await;
+    }
+    let _ = signal;
+    std::future::pending().await
+}
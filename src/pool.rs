@@ -0,0 +1,186 @@
+//! Long-lived shell workers used when `exec-mode = "pool"`.
+//!
+//! Each worker is an `sh` process reading commands from its stdin. A command is
+//! sent as a framed script that runs it in a subshell and then prints a marker
+//! line carrying the exit status, so the reader knows where its output ends.
+//!
+//! Caveats compared to `spawn`:
+//! - Commands run in a subshell, so `cd`, `export`, variables and traps do not
+//!   carry over, but anything the worker itself inherited at startup (its
+//!   environment, user, limits) is fixed for the worker's lifetime. That
+//!   includes the `env-allow`/`env-deny` filtering in effect when it started.
+//!   Actions with `run-as-user` or `run-as-group` are always spawned instead.
+//! - stdout and stderr are merged into a single stream.
+//! - Background jobs (`cmd &`) that keep writing after the command returns end up
+//!   in the output of whatever runs next on that worker.
+//! - If a command is abandoned (for example by `max-total-duration-ms`), its
+//!   worker is killed and replaced, but processes it started may outlive it.
+
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+static POOL: OnceLock<ShellPool> = OnceLock::new();
+static NEXT_WORKER: AtomicU64 = AtomicU64::new(0);
+
+pub struct PooledCommand<'a> {
+    pub command_line: &'a str,
+    pub working_dir: Option<&'a PathBuf>,
+    pub envs: &'a [(String, String)],
+    // Environment for a worker started to run this command, if not kadesh's own.
+    pub inherited_env: Option<&'a [(OsString, OsString)]>,
+    pub umask: Option<u32>,
+}
+
+pub struct PooledOutput {
+    pub code: i32,
+    pub output: Vec<u8>,
+}
+
+pub struct ShellPool {
+    idle: Mutex<Vec<Worker>>,
+    slots: Semaphore,
+}
+
+// The pool is created on first use and sized from the config at that point.
+pub fn shared(size: usize) -> &'static ShellPool {
+    POOL.get_or_init(|| ShellPool {
+        idle: Mutex::new(Vec::with_capacity(size)),
+        slots: Semaphore::new(size),
+    })
+}
+
+impl ShellPool {
+    pub async fn run(&self, command: &PooledCommand<'_>) -> std::io::Result<PooledOutput> {
+        let _slot = self
+            .slots
+            .acquire()
+            .await
+            .map_err(|_| Error::other("shell pool closed"))?;
+        let idle = self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => Worker::spawn(command.inherited_env)?,
+        };
+
+        // A worker is only returned to the pool once its output is fully read. If
+        // it dies, or this future is dropped mid-command, it is dropped (and
+        // killed) instead, and the next caller spawns a replacement.
+        match worker.run(command).await {
+            Ok(output) => {
+                self.idle
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(worker);
+                Ok(output)
+            }
+            Err(e) => {
+                warn!(worker = worker.id, error = %e, "Shell worker failed, it will be replaced");
+                Err(e)
+            }
+        }
+    }
+}
+
+struct Worker {
+    id: u64,
+    marker: String,
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    fn spawn(inherited_env: Option<&[(OsString, OsString)]>) -> std::io::Result<Self> {
+        let id = NEXT_WORKER.fetch_add(1, Ordering::Relaxed);
+        let mut command = Command::new("sh");
+        if let Some(inherited) = inherited_env {
+            command.env_clear();
+            command.envs(inherited.iter().map(|(key, value)| (key, value)));
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| Error::other("worker has no stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| Error::other("worker has no stdout"))?;
+        debug!(worker = id, "Started shell worker");
+        Ok(Worker {
+            id,
+            marker: format!("__kadesh_done_{}_{}", std::process::id(), id),
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    async fn run(&mut self, command: &PooledCommand<'_>) -> std::io::Result<PooledOutput> {
+        let script = self.frame(command);
+        self.stdin.write_all(script.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        let mut output = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.stdout.read_until(b'\n', &mut line).await? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "shell worker exited before the command finished",
+                ));
+            }
+            if let Some(status) = line.strip_prefix(self.marker.as_bytes()) {
+                let code = String::from_utf8_lossy(status)
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::other("malformed status line from shell worker"))?;
+                // The marker is always preceded by a newline of its own.
+                if output.last() == Some(&b'\n') {
+                    output.pop();
+                }
+                return Ok(PooledOutput { code, output });
+            }
+            output.extend_from_slice(&line);
+        }
+    }
+
+    fn frame(&self, command: &PooledCommand<'_>) -> String {
+        let mut script = String::from("(\n");
+        if let Some(dir) = command.working_dir {
+            script.push_str(&format!("cd {} || exit 1\n", quote(&dir.to_string_lossy())));
+        }
+        if let Some(mask) = command.umask {
+            script.push_str(&format!("umask {:03o}\n", mask));
+        }
+        for (key, value) in command.envs {
+            script.push_str(&format!("export {}={}\n", key, quote(value)));
+        }
+        script.push_str(&format!("eval {}\n", quote(command.command_line)));
+        script.push_str(") </dev/null 2>&1\n");
+        script.push_str(&format!("printf '\\n%s %d\\n' '{}' \"$?\"\n", self.marker));
+        script
+    }
+}
+
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
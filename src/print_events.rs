@@ -0,0 +1,64 @@
+use crate::clock;
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use crate::watcher::FsWatcher;
+use notify::RecursiveMode;
+use notify_debouncer_full::DebounceEventResult;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+// Watches `dir` recursively and prints every debounced event as it comes out of
+// the debouncer, one per line, until Ctrl+C. No config is read: filters and
+// actions don't apply, so this shows what the platform actually reports.
+pub async fn run(dir: &Path, debounce_ms: u64) -> Result<()> {
+    let dir = dir.canonicalize()?;
+    let config = Config {
+        debounce_ms,
+        ..Config::default()
+    };
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<DebounceEventResult>();
+    let mut watcher = FsWatcher::new(&config, move |result| {
+        let _ = event_tx.send(result);
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .map_err(AppError::Notify)?;
+    eprintln!(
+        "Printing events under {} ({}ms debounce). Press Ctrl+C to stop.",
+        dir.display(),
+        debounce_ms
+    );
+
+    loop {
+        let result = tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            result = event_rx.recv() => match result {
+                Some(result) => result,
+                None => return Ok(()),
+            },
+        };
+        match result {
+            Ok(events) => {
+                for event in events {
+                    let time = clock::wall_clock(event.time);
+                    let paths: Vec<String> = event
+                        .paths
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect();
+                    println!(
+                        "{} {:?} {}",
+                        humantime::format_rfc3339_millis(time),
+                        event.kind,
+                        paths.join(" ")
+                    );
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("error: {}", error);
+                }
+            }
+        }
+    }
+}
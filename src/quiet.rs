@@ -0,0 +1,29 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+// Past this many paths, entries whose quiet period has passed are pruned.
+const MAX_TRACKED_PATHS: usize = 1024;
+
+// When an action last finished on each path, per watch.
+static ACTED: LazyLock<Mutex<HashMap<(u64, PathBuf), Instant>>> = LazyLock::new(Mutex::default);
+
+pub fn record(watch_id: u64, path: &Path, quiet: Duration) {
+    let now = Instant::now();
+    let mut acted = ACTED.lock().unwrap_or_else(PoisonError::into_inner);
+    if acted.len() >= MAX_TRACKED_PATHS {
+        acted.retain(|_, finished| now.duration_since(*finished) < quiet);
+    }
+    acted.insert((watch_id, path.to_path_buf()), now);
+}
+
+pub fn is_quiet(watch_id: u64, path: &Path, quiet: Duration) -> bool {
+    ACTED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&(watch_id, path.to_path_buf()))
+        .is_some_and(|finished| finished.elapsed() < quiet)
+}
@@ -0,0 +1,115 @@
+//! `max-actions-per-second`: a global token bucket in front of every action
+//! dispatch, across all watches. It bounds how often actions start, not how
+//! many run at once. The bucket holds up to one second's worth of tokens, so
+//! a quiet period allows a burst of at most that many.
+
+use crate::config::RateLimitPolicy;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+static BUCKET: LazyLock<Mutex<Bucket>> = LazyLock::new(|| {
+    Mutex::new(Bucket {
+        tokens: None,
+        refilled: Instant::now(),
+    })
+});
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+struct Bucket {
+    // Goes negative while `wait` has handed out slots ahead of time. None until
+    // first used, when the bucket starts full.
+    tokens: Option<f64>,
+    refilled: Instant,
+}
+
+// How long the action has to wait for its slot, or None if it is dropped.
+// Slots are taken in call order, so waiting actions keep their dispatch order.
+pub fn reserve(per_second: u32, policy: RateLimitPolicy) -> Option<Duration> {
+    let mut bucket = BUCKET.lock().unwrap_or_else(PoisonError::into_inner);
+    let reserved = bucket.take(per_second, policy, Instant::now());
+    if reserved.is_none() {
+        // Warn on the first drop and then every 1000th, not for each action.
+        let dropped = DROPPED.fetch_add(1, Ordering::Relaxed);
+        if dropped.is_multiple_of(1000) {
+            warn!(
+                dropped = dropped + 1,
+                max_actions_per_second = per_second,
+                "Action rate limit reached, dropping actions"
+            );
+        }
+    }
+    reserved
+}
+
+impl Bucket {
+    fn take(&mut self, per_second: u32, policy: RateLimitPolicy, now: Instant) -> Option<Duration> {
+        let rate = f64::from(per_second);
+        let refill = now.duration_since(self.refilled).as_secs_f64() * rate;
+        let tokens = self
+            .tokens
+            .map_or(rate, |tokens| (tokens + refill).min(rate));
+        self.refilled = now;
+        if tokens >= 1.0 {
+            self.tokens = Some(tokens - 1.0);
+            return Some(Duration::ZERO);
+        }
+        match policy {
+            RateLimitPolicy::Wait => {
+                self.tokens = Some(tokens - 1.0);
+                Some(Duration::from_secs_f64((1.0 - tokens) / rate))
+            }
+            RateLimitPolicy::Drop => {
+                self.tokens = Some(tokens);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(now: Instant) -> Bucket {
+        Bucket {
+            tokens: None,
+            refilled: now,
+        }
+    }
+
+    #[test]
+    fn a_burst_beyond_the_rate_is_dropped() {
+        let start = Instant::now();
+        let mut bucket = bucket(start);
+        let admitted = (0..25)
+            .filter(|_| bucket.take(10, RateLimitPolicy::Drop, start).is_some())
+            .count();
+        assert_eq!(admitted, 10);
+        // Half a second refills half the bucket.
+        let later = start + Duration::from_millis(500);
+        let admitted = (0..25)
+            .filter(|_| bucket.take(10, RateLimitPolicy::Drop, later).is_some())
+            .count();
+        assert_eq!(admitted, 5);
+    }
+
+    #[test]
+    fn a_waiting_burst_is_spread_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = bucket(start);
+        let waits: Vec<_> = (0..30)
+            .map(|_| bucket.take(10, RateLimitPolicy::Wait, start).unwrap())
+            .collect();
+        assert!(waits[..10].iter().all(|wait| wait.is_zero()));
+        // Each action past the first second's worth starts 100ms after the last.
+        for (n, wait) in waits[10..].iter().enumerate() {
+            let expected = Duration::from_millis(100 * (n as u64 + 1));
+            assert!(
+                wait.abs_diff(expected) < Duration::from_millis(1),
+                "{n}: {wait:?}"
+            );
+        }
+    }
+}
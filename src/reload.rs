@@ -0,0 +1,250 @@
+use crate::config::{self, Config, STDIN_CONFIG_PATH, WatchConfig};
+use crate::errors::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+// Re-reads the config file on SIGHUP and publishes it to the event processor,
+// which applies it. A config that fails to load, validate or resolve its watch
+// paths is logged and the running one kept whole: nothing is published and no
+// watch is touched. The receiver always holds the config currently in effect.
+// `overrides` applies command-line settings on top of each reloaded config.
+pub fn spawn_listener(
+    path: PathBuf,
+    current: Arc<Config>,
+    overrides: impl Fn(&mut Config) + Send + Sync + 'static,
+) -> watch::Receiver<Arc<Config>> {
+    let (config_tx, config_rx) = watch::channel(current);
+    tokio::spawn(async move {
+        let mut signal = reload_signal(&path);
+        while let Some(()) = recv(&mut signal).await {
+            info!(path = %path.display(), "SIGHUP received, reloading configuration");
+            match prepare(&path, &overrides).await {
+                Ok(config) => {
+                    inherit_ids(&config_tx.borrow(), &config);
+                    warn_restart_only(&config_tx.borrow(), &config);
+                    if config_tx.send(Arc::new(config)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to reload configuration, keeping the current one")
+                }
+            }
+        }
+    });
+    config_rx
+}
+
+// Loads and validates the config, applies the overrides and resolves the watch
+// roots. Nothing is published unless all of it succeeds.
+async fn prepare(path: &Path, overrides: &impl Fn(&mut Config)) -> Result<Config> {
+    let mut config = config::load_config(path).await?;
+    overrides(&mut config);
+    resolve_watches(config)
+}
+
+// Watch roots are resolved before the config is published rather than when they
+// are registered, so a path that no longer expands rejects the whole reload
+// instead of dropping its watch.
+fn resolve_watches(config: Config) -> Result<Config> {
+    for watch in &config.watches {
+        watch.expanded_absolute_path()?;
+    }
+    Ok(config)
+}
+
+// Each watch that is still configured, with the same name and root, keeps its
+// id so the state kept for it follows it to its new position. Done before the
+// config is published, as ids are fixed once read.
+fn inherit_ids(old: &Config, new: &Config) {
+    let mut unclaimed: Vec<_> = old.watches.iter().collect();
+    for watch in &new.watches {
+        let same = |previous: &&WatchConfig| {
+            previous.display_name() == watch.display_name()
+                && previous.resolved_root() == watch.resolved_root()
+        };
+        if let Some(position) = unclaimed.iter().position(same) {
+            watch.inherit_id(unclaimed.remove(position));
+        }
+    }
+}
+
+// Whether the set of registered watches differs, as opposed to only what is done
+// with their events. These are the settings registration reads: where and how
+// each entry is watched, how many watches it is projected to take against
+// `max-watches`, and whether its files' tail offsets get primed.
+pub fn watches_changed(old: &Config, new: &Config) -> bool {
+    let topology = |config: &Config| {
+        let watches = config
+            .watches
+            .iter()
+            .map(|watch| {
+                (
+                    watch.path.clone(),
+                    watch.resolved_root().map(Path::to_path_buf),
+                    watch.recursive,
+                    watch.track_symlink,
+                    watch.same_filesystem,
+                    watch.allow_missing,
+                    watch.tail,
+                )
+            })
+            .collect::<Vec<_>>();
+        (watches, config.max_watches)
+    };
+    topology(old) != topology(new)
+}
+
+// Settings read once at startup. They are still swapped in, but keep their old
+// effect until kadesh is restarted.
+fn warn_restart_only(old: &Config, new: &Config) {
+    let changed = [
+        ("log-level", old.log_level != new.log_level),
+        ("log-targets", old.log_targets != new.log_targets),
+        ("debounce-ms", old.debounce_ms != new.debounce_ms),
+        ("file-id-cache", old.file_id_cache != new.file_id_cache),
+        (
+            "dedup-window-ms",
+            old.dedup_window_ms != new.dedup_window_ms,
+        ),
+        (
+            "ignore-transient",
+            old.ignore_transient != new.ignore_transient,
+        ),
+        (
+            "transient-window-ms",
+            old.transient_window_ms != new.transient_window_ms,
+        ),
+        (
+            "debounce-by-kind",
+            old.debounce_by_kind != new.debounce_by_kind,
+        ),
+        ("pool-size", old.pool_size != new.pool_size),
+        ("pause-file", old.pause_file != new.pause_file),
+        (
+            "heartbeat-interval-ms",
+            old.heartbeat_interval_ms != new.heartbeat_interval_ms,
+        ),
+    ];
+    for (setting, _) in changed.iter().filter(|(_, changed)| *changed) {
+        warn!(setting, "Changed setting only takes effect after a restart");
+    }
+}
+
+#[cfg(unix)]
+type ReloadSignal = Option<tokio::signal::unix::Signal>;
+#[cfg(not(unix))]
+type ReloadSignal = Option<()>;
+
+fn reload_signal(path: &Path) -> ReloadSignal {
+    if path == Path::new(STDIN_CONFIG_PATH) {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                warn!(error = %e, "Cannot listen for SIGHUP, configuration reload is unavailable");
+                None
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+async fn recv(signal: &mut ReloadSignal) -> Option<()> {
+    #[cfg(unix)]
+    if let Some(signal) = signal {
+        return signal.recv().await;
+    }
+    let _ = signal;
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    async fn prepare_toml(toml: &str) -> Result<Config> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), toml).unwrap();
+        prepare(file.path(), &|_: &mut Config| {}).await
+    }
+
+    #[tokio::test]
+    async fn a_reload_that_fails_validation_publishes_nothing() {
+        assert!(prepare_toml(WATCH).await.is_ok());
+        let unknown_event = WATCH.replace("\"create\"", "\"created\"");
+        assert!(prepare_toml(&unknown_event).await.is_err());
+        let unexpandable = WATCH.replace("/srv/in", "$KADESH_TEST_UNSET_ROOT/in");
+        assert!(prepare_toml(&unexpandable).await.is_err());
+        assert!(prepare_toml("[[watch]]\npath = ").await.is_err());
+    }
+
+    const WATCH: &str = r#"
+[[watch]]
+path = "/srv/in"
+[[watch.actions]]
+event = "create"
+command = "true"
+"#;
+
+    #[test]
+    fn actions_alone_leave_the_watches_registered() {
+        let old = config(WATCH);
+        let new = config(&WATCH.replace("\"true\"", "\"false\""));
+        assert!(!watches_changed(&old, &new));
+    }
+
+    #[test]
+    fn registration_settings_re_register_the_watches() {
+        let old = config(WATCH);
+        for setting in [
+            "same-filesystem = true",
+            "allow-missing = true",
+            "tail = true",
+            "recursive = true",
+        ] {
+            let new = config(&WATCH.replace(
+                "path = \"/srv/in\"",
+                &format!("path = \"/srv/in\"\n{setting}"),
+            ));
+            assert!(watches_changed(&old, &new), "{setting}");
+        }
+        let capped = config(&format!("max-watches = 10\n{WATCH}"));
+        assert!(watches_changed(&old, &capped));
+    }
+
+    fn resolved(toml: &str) -> Config {
+        resolve_watches(config(toml)).unwrap()
+    }
+
+    #[test]
+    fn reordered_watches_keep_their_state() {
+        let watch = |name: &str| format!("[[watch]]\nname = \"{name}\"\npath = \"/srv/{name}\"\n");
+        let old = resolved(&format!("{}{}", watch("a"), watch("b")));
+        let new = resolved(&format!("{}{}{}", watch("b"), watch("c"), watch("a")));
+        inherit_ids(&old, &new);
+        assert_eq!(new.watches[0].id(), old.watches[1].id());
+        assert_eq!(new.watches[2].id(), old.watches[0].id());
+        assert!(old.watches.iter().all(|w| w.id() != new.watches[1].id()));
+
+        let path = Path::new("/srv/b/report.txt");
+        assert!(crate::once::first_time(&old.watches[1], path));
+        assert!(!crate::once::first_time(&new.watches[0], path));
+        assert!(crate::once::first_time(&new.watches[2], path));
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::{Path, PathBuf};
+
+// Every file under `root`, or directly in it unless `recursive`. Symlinks are
+// not followed, and directories that can't be read are skipped.
+pub fn files(root: &Path, recursive: bool) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => {
+                    if recursive {
+                        dirs.push(entry.path());
+                    }
+                }
+                Ok(_) => files.push(entry.path()),
+                Err(_) => {}
+            }
+        }
+    }
+    files
+}
@@ -0,0 +1,88 @@
+use crate::config::Action;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+// Past this many throttled paths, entries whose window has passed are pruned.
+const MAX_TRACKED_PATHS: usize = 1024;
+
+static SAMPLER: LazyLock<Mutex<Sampler>> = LazyLock::new(Mutex::default);
+
+// Identifies an action across events: the watch it fired for and what it runs.
+type ActionKey = (u64, String);
+
+#[derive(Default)]
+struct Sampler {
+    seen: HashMap<ActionKey, u64>,
+    last_run: HashMap<(ActionKey, PathBuf), Instant>,
+}
+
+// Both policies drop: a suppressed event is gone, it is not deferred or merged
+// into the next run. `throttle-ms` is checked first, and only events it lets
+// through count towards `sample-rate`.
+pub fn admit(watch_id: u64, action: &Action, path: &Path, now: Instant) -> bool {
+    if action.throttle_ms.is_none() && action.sample_rate.is_none_or(|rate| rate <= 1) {
+        return true;
+    }
+    let key = (watch_id, action.describe());
+    let mut sampler = SAMPLER.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(throttle_ms) = action.throttle_ms {
+        let window = Duration::from_millis(throttle_ms);
+        if sampler.last_run.len() >= MAX_TRACKED_PATHS {
+            sampler
+                .last_run
+                .retain(|_, ran| now.duration_since(*ran) < window);
+        }
+        let path_key = (key.clone(), path.to_path_buf());
+        if let Some(ran) = sampler.last_run.get(&path_key)
+            && now.duration_since(*ran) < window
+        {
+            return false;
+        }
+        sampler.last_run.insert(path_key, now);
+    }
+
+    if let Some(rate) = action.sample_rate.filter(|rate| *rate > 1) {
+        let seen = sampler.seen.entry(key).or_default();
+        let admitted = seen.is_multiple_of(rate);
+        *seen += 1;
+        return admitted;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(toml: &str) -> Action {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn sample_rate_keeps_every_nth_event() {
+        let action = action("event = \"any\"\ncommand = \"sampled\"\nsample-rate = 3");
+        let now = Instant::now();
+        let admitted: Vec<_> = (0..7)
+            .map(|n| admit(0, &action, Path::new(&format!("/srv/{n}")), now))
+            .collect();
+        assert_eq!(admitted, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn throttle_drops_a_burst_on_one_path() {
+        let action = action("event = \"any\"\ncommand = \"throttled\"\nthrottle-ms = 100");
+        let (path, other) = (Path::new("/srv/a"), Path::new("/srv/b"));
+        let now = Instant::now();
+        assert!(admit(0, &action, path, now));
+        assert!(!admit(0, &action, path, now + Duration::from_millis(50)));
+        assert!(admit(0, &action, other, now + Duration::from_millis(50)));
+        // Another watch running the same command is throttled on its own.
+        assert!(admit(1, &action, path, now + Duration::from_millis(50)));
+        assert!(admit(0, &action, path, now + Duration::from_millis(100)));
+    }
+}
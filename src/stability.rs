@@ -0,0 +1,108 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime},
+};
+use tracing::debug;
+
+// What is compared between polls; a file is stable once neither changes.
+type Snapshot = (u64, Option<SystemTime>);
+
+// Paths being waited on, per watch, with how many events arrived for each
+// since the wait started.
+static WAITING: LazyLock<Mutex<HashMap<(u64, PathBuf), u64>>> = LazyLock::new(Mutex::default);
+
+fn waiting() -> MutexGuard<'static, HashMap<(u64, PathBuf), u64>> {
+    WAITING.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn snapshot(path: &Path) -> Option<Snapshot> {
+    let metadata = path.metadata().ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+// Notes an event for `path`. Returns true when nothing was waiting on it yet,
+// in which case the caller starts `settled`; otherwise the running wait starts
+// its count over and this event is folded into it.
+pub fn note_change(watch_id: u64, path: &Path) -> bool {
+    match waiting().entry((watch_id, path.to_path_buf())) {
+        Entry::Occupied(mut entry) => {
+            *entry.get_mut() += 1;
+            false
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(0);
+            true
+        }
+    }
+}
+
+// Waits until `path` has looked the same for `checks` polls in a row,
+// `interval` apart, with no event noted for it in between. A file that keeps
+// changing is waited on for as long as it does; false if it disappears. Either
+// way the path is no longer waited on afterwards, so its next event starts anew.
+pub async fn settled(watch_id: u64, path: &Path, checks: u32, interval: Duration) -> bool {
+    let key = (watch_id, path.to_path_buf());
+    let mut seen = snapshot(path);
+    let mut changes = 0;
+    let mut unchanged = 0;
+    let stable = loop {
+        if seen.is_none() {
+            debug!(path = %path.display(), "File disappeared during the stability check");
+            break false;
+        }
+        tokio::time::sleep(interval).await;
+        let noted = waiting().get(&key).copied().unwrap_or(changes);
+        let now = snapshot(path);
+        if now == seen && noted == changes {
+            unchanged += 1;
+            if unchanged >= checks {
+                break true;
+            }
+        } else {
+            unchanged = 0;
+            changes = noted;
+        }
+        seen = now;
+    };
+    waiting().remove(&key);
+    stable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    #[tokio::test]
+    async fn later_events_join_the_running_wait() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload.bin");
+        std::fs::write(&path, "a").unwrap();
+        assert!(note_change(0, &path));
+        assert!(!note_change(0, &path));
+        // Another watch waits on its own.
+        assert!(note_change(1, &path));
+        assert!(settled(0, &path, 2, INTERVAL).await);
+        assert!(settled(1, &path, 2, INTERVAL).await);
+        assert!(note_change(0, &path));
+        assert!(settled(0, &path, 1, INTERVAL).await);
+    }
+
+    #[tokio::test]
+    async fn a_vanished_file_never_settles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload.bin");
+        std::fs::write(&path, "a").unwrap();
+        assert!(note_change(0, &path));
+        let wait = tokio::spawn({
+            let path = path.clone();
+            async move { settled(0, &path, 3, INTERVAL).await }
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert!(!wait.await.unwrap());
+        assert!(note_change(0, &path));
+    }
+}
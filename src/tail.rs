@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, PoisonError},
+};
+use tracing::{debug, warn};
+
+// Read offset per followed file. Held across the read so two events for the
+// same file never hand out the same lines twice.
+static OFFSETS: LazyLock<Mutex<HashMap<PathBuf, u64>>> = LazyLock::new(Mutex::default);
+
+// Records the current size of every file under `root`, so only lines written
+// after startup are picked up. Files already followed keep their offset, so a
+// reload does not skip lines that were not read yet.
+pub fn prime(root: &Path, recursive: bool) {
+    let mut offsets = OFFSETS.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = path.metadata() else {
+            continue;
+        };
+        if metadata.is_file() {
+            offsets.entry(path).or_insert(metadata.len());
+        } else if metadata.is_dir()
+            && (recursive || path == root)
+            && let Ok(entries) = std::fs::read_dir(&path)
+        {
+            pending.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+}
+
+pub fn forget(path: &Path) {
+    OFFSETS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(path);
+}
+
+// Returns the complete lines appended since the last call. A trailing partial
+// line is left for next time. If the file shrank it was truncated or replaced,
+// and reading restarts from the beginning.
+pub fn read_new_lines(path: &Path) -> Vec<String> {
+    let mut offsets = OFFSETS.lock().unwrap_or_else(PoisonError::into_inner);
+    let offset = offsets.entry(path.to_path_buf()).or_default();
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            debug!(path = %path.display(), error = %e, "Cannot open followed file");
+            return Vec::new();
+        }
+    };
+    let len = match file.metadata() {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return Vec::new(),
+    };
+    if len < *offset {
+        debug!(path = %path.display(), "Followed file shrank, reading from the start");
+        *offset = 0;
+    }
+    let mut appended = Vec::new();
+    if let Err(e) = file
+        .seek(SeekFrom::Start(*offset))
+        .and_then(|_| file.read_to_end(&mut appended))
+    {
+        warn!(path = %path.display(), error = %e, "Failed to read followed file");
+        return Vec::new();
+    }
+    let Some(end) = appended.iter().rposition(|b| *b == b'\n') else {
+        return Vec::new();
+    };
+    *offset += end as u64 + 1;
+    String::from_utf8_lossy(&appended[..end])
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,83 @@
+use notify::EventKind;
+use notify::event::ModifyKind;
+use notify_debouncer_full::DebouncedEvent;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+// Holds each create event, and whatever follows on the same path, for
+// `transient-window-ms` so that a file created and removed again within the
+// window, like an editor's or build tool's temp file, is dropped together with
+// everything that happened to it. Files that outlive the window have their
+// events released late by that much. The debouncer already drops pairs that
+// fall within one debounce period; this catches the ones spanning several.
+pub struct TransientFilter {
+    window: Duration,
+    pending: HashMap<PathBuf, (Vec<DebouncedEvent>, Instant)>,
+}
+
+impl TransientFilter {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+    // The events to pass on now, in order. A rename releases what is held for
+    // its paths first, as the file then lives on under another name.
+    pub fn push(&mut self, event: DebouncedEvent, now: Instant) -> Vec<DebouncedEvent> {
+        if self.window.is_zero() {
+            return vec![event];
+        }
+        let is_rename = matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
+        let path = match event.paths.as_slice() {
+            [path] if !is_rename => path,
+            paths => {
+                let mut events = self.release(paths);
+                events.push(event);
+                return events;
+            }
+        };
+        if event.kind.is_remove() && self.pending.remove(path).is_some() {
+            tracing::debug!(path = %path.display(), "Ignoring transient file");
+            return Vec::new();
+        }
+        if let Some((held, _)) = self.pending.get_mut(path) {
+            held.push(event);
+            return Vec::new();
+        }
+        if event.kind.is_create() {
+            let deadline = now + self.window;
+            self.pending.insert(path.clone(), (vec![event], deadline));
+            return Vec::new();
+        }
+        vec![event]
+    }
+
+    fn release(&mut self, paths: &[PathBuf]) -> Vec<DebouncedEvent> {
+        paths
+            .iter()
+            .filter_map(|path| self.pending.remove(path))
+            .flat_map(|(events, _)| events)
+            .collect()
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|(_, deadline)| *deadline).min()
+    }
+
+    pub fn take_due(&mut self, now: Instant) -> Vec<DebouncedEvent> {
+        let due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut events = self.release(&due);
+        events.sort_by_key(|event| event.time);
+        events
+    }
+}
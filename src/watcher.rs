@@ -0,0 +1,117 @@
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use notify::{INotifyWatcher, RecursiveMode, Watcher};
+use notify_debouncer_full::{
+    DebounceEventHandler, DebouncedEvent, Debouncer, FileIdMap, NoCache, new_debouncer_opt,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+// The debouncer is generic over its file-ID cache, so the configured variant is
+// picked at startup and wrapped here to give the rest of kadesh a single type.
+// With `debounce-ms = 0` there is no debouncer at all: each raw event is handed
+// on as soon as notify reports it, as a batch of one.
+enum Backend {
+    Uncached(Debouncer<INotifyWatcher, NoCache>),
+    Cached(Debouncer<INotifyWatcher, FileIdMap>),
+    Raw(INotifyWatcher),
+}
+
+// Paths are tracked as they are registered and unregistered, so the set stays
+// current across reloads and re-established roots.
+pub struct FsWatcher {
+    backend: Backend,
+    registered: BTreeMap<PathBuf, RecursiveMode>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WatchedPath {
+    pub path: PathBuf,
+    pub recursive: bool,
+    // False while the path does not exist, e.g. a root that was removed and is
+    // waiting to reappear. inotify has dropped its watch by then.
+    pub active: bool,
+}
+
+impl FsWatcher {
+    pub fn new<F: DebounceEventHandler>(config: &Config, mut event_handler: F) -> Result<Self> {
+        let timeout = Duration::from_millis(config.debounce_ms);
+        let backend = if timeout.is_zero() {
+            Backend::Raw(
+                INotifyWatcher::new(
+                    move |result: notify::Result<notify::Event>| {
+                        event_handler.handle_event(
+                            result
+                                .map(|event| vec![DebouncedEvent::new(event, Instant::now())])
+                                .map_err(|e| vec![e]),
+                        )
+                    },
+                    notify::Config::default(),
+                )
+                .map_err(AppError::Notify)?,
+            )
+        } else if config.file_id_cache {
+            Backend::Cached(
+                new_debouncer_opt(
+                    timeout,
+                    None,
+                    event_handler,
+                    FileIdMap::new(),
+                    notify::Config::default(),
+                )
+                .map_err(AppError::Debounce)?,
+            )
+        } else {
+            Backend::Uncached(
+                new_debouncer_opt(
+                    timeout,
+                    None,
+                    event_handler,
+                    NoCache,
+                    notify::Config::default(),
+                )
+                .map_err(AppError::Debounce)?,
+            )
+        };
+        Ok(FsWatcher {
+            backend,
+            registered: BTreeMap::new(),
+        })
+    }
+
+    pub fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        match &mut self.backend {
+            Backend::Uncached(debouncer) => debouncer.watch(path, recursive_mode),
+            Backend::Cached(debouncer) => debouncer.watch(path, recursive_mode),
+            Backend::Raw(watcher) => watcher.watch(path, recursive_mode),
+        }?;
+        self.registered.insert(path.to_path_buf(), recursive_mode);
+        Ok(())
+    }
+
+    // The path is forgotten even if notify fails to remove it, which happens
+    // when inotify already dropped the watch along with the path.
+    pub fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        self.registered.remove(path);
+        match &mut self.backend {
+            Backend::Uncached(debouncer) => debouncer.unwatch(path),
+            Backend::Cached(debouncer) => debouncer.unwatch(path),
+            Backend::Raw(watcher) => watcher.unwatch(path),
+        }
+    }
+
+    pub fn watched_paths(&self) -> Vec<WatchedPath> {
+        self.registered
+            .iter()
+            .map(|(path, mode)| WatchedPath {
+                path: path.clone(),
+                recursive: *mode == RecursiveMode::Recursive,
+                active: path.exists(),
+            })
+            .collect()
+    }
+}